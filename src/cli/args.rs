@@ -1,9 +1,14 @@
-use clap::{command, ArgGroup, Args, Parser, Subcommand};
-use std::{ffi::OsString, ops::RangeInclusive, path::PathBuf};
+use chrono::{Duration, NaiveDateTime};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use std::{ffi::OsString, net::SocketAddr, ops::RangeInclusive, path::PathBuf};
 
 use crate::{
-    models::ScoreFilter,
-    utils::common::{parse_range, parse_score_filters},
+    models::{Color, ColorTheme, ScoreFilter, ThumbnailSize},
+    utils::common::{
+        parse_color, parse_datetime, parse_dimensions, parse_duration, parse_hex_color,
+        parse_range, parse_score_filters, parse_size, parse_theme, parse_thumbnail_size,
+        META_PATH_VAR, ROOT_VAR, TOKEN_VAR,
+    },
 };
 
 #[derive(Debug, Parser)]
@@ -14,6 +19,18 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
 
+    /// Path of the configuration file to use
+    #[arg(long = "config", global = true)]
+    pub config_file: Option<PathBuf>,
+
+    /// Override the root images directory
+    #[arg(long = "root", global = true, env = ROOT_VAR, hide_env_values = true)]
+    pub root_images_dir: Option<PathBuf>,
+
+    /// Override the path of the metadata file
+    #[arg(long = "meta", global = true, env = META_PATH_VAR, hide_env_values = true)]
+    pub metadata_path: Option<PathBuf>,
+
     #[command(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity,
 }
@@ -35,39 +52,323 @@ pub enum Commands {
     /// List images that match given selectors
     #[command(name = "list", alias = "ls")]
     List {
-        /// Filter based on parent directories
-        #[arg(short = 'd', long = "directories")]
-        active_directories: Option<Vec<PathBuf>>,
+        #[command(flatten)]
+        filters: FilterArgs,
 
-        /// Filter based on score range
-        #[arg(short = 's', long = "scores", value_parser = parse_score_filters)]
-        scores: Option<Vec<ScoreFilter>>,
+        /// Output in JSON
+        #[arg(short = 'j', long = "json")]
+        use_json_format: bool,
+    },
+    /// Scan the entire images directory to find missing data
+    Scan {
+        /// Only scan the root with this name
+        #[arg(short = 'r', long = "root-name")]
+        root_name: Option<String>,
 
-        /// Filter based on width range
-        #[arg(short = 'W', long = "width", value_parser = parse_range)]
-        width_range: Option<RangeInclusive<usize>>,
+        /// Fully decode images to find truncated, corrupt or unsupported files
+        #[arg(long = "deep")]
+        deep: bool,
 
-        /// Filter based on height range
-        #[arg(short = 'H', long = "height", value_parser = parse_range)]
-        height_range: Option<RangeInclusive<usize>>,
+        /// Output in JSON
+        #[arg(short = 'j', long = "json")]
+        use_json_format: bool,
+    },
+    /// Re-hash images and compare them against their metadata ID to detect bit rot
+    Verify {
+        #[command(flatten)]
+        filters: FilterArgs,
 
-        #[arg(short = 't', long = "tags")]
-        tags: Option<Vec<String>>,
+        /// Only verify images that have not been verified for this long (e.g. 12h, 30d, 2w)
+        #[arg(short = 'o', long = "older-than", value_parser = parse_duration)]
+        older_than: Option<Duration>,
 
-        /// Ignore selectors preset from config
-        #[arg(short = 'i', long = "ignore")]
-        ignore_config: bool,
+        /// Stop after hashing this amount of data (e.g. 500MB, 10GB)
+        #[arg(short = 'b', long = "budget", value_parser = parse_size)]
+        budget: Option<u64>,
 
         /// Output in JSON
         #[arg(short = 'j', long = "json")]
         use_json_format: bool,
     },
-    /// Scan the entire images directory to find missing data
-    Scan {
+    /// Find byte-identical images and deduplicate them
+    Dupes {
+        /// Only look for duplicates in the root with this name
+        #[arg(short = 'r', long = "root-name")]
+        root_name: Option<String>,
+
+        /// Group visually similar images instead of byte-identical ones
+        #[arg(short = 'P', long = "perceptual")]
+        perceptual: bool,
+
+        /// Maximum number of differing bits (0-64) between perceptual hashes of similar images
+        #[arg(
+            short = 't',
+            long = "threshold",
+            default_value_t = 10,
+            requires = "perceptual",
+            value_parser = clap::value_parser!(u32).range(0..=64)
+        )]
+        threshold: u32,
+
+        /// Which copy of each group to keep [default: resolution with --perceptual, oldest otherwise]
+        #[arg(short = 'k', long = "keep", value_enum)]
+        keep: Option<KeepStrategy>,
+
+        /// What to do with the other copies
+        #[arg(short = 'a', long = "action", value_enum, default_value_t = DupesAction::List)]
+        action: DupesAction,
+
+        /// Show what would be done without touching any file
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+
         /// Output in JSON
         #[arg(short = 'j', long = "json")]
         use_json_format: bool,
     },
+    /// Find images that look like the given one, using perceptual hashes
+    Similar {
+        /// Metadata ID or path of the image file
+        identifier: OsString,
+
+        /// Maximum number of differing bits (0-64) between perceptual hashes
+        #[arg(
+            short = 't',
+            long = "threshold",
+            default_value_t = 10,
+            value_parser = clap::value_parser!(u32).range(0..=64)
+        )]
+        threshold: u32,
+
+        /// Only look for similar images in the root with this name
+        #[arg(short = 'r', long = "root-name")]
+        root_name: Option<String>,
+
+        /// Output in JSON
+        #[arg(short = 'j', long = "json")]
+        use_json_format: bool,
+    },
+
+    /// Export images that match given selectors
+    Export {
+        #[command(subcommand)]
+        command: ExportCommands,
+    },
+
+    /// Print the path of the thumbnail of an image, creating it in the shared thumbnail cache
+    Thumb {
+        /// Metadata ID or path of the image file
+        #[arg(required_unless_present = "all")]
+        identifier: Option<OsString>,
+
+        /// Size of the thumbnail (normal, large, x-large)
+        #[arg(long = "size", default_value = "normal", value_parser = parse_thumbnail_size)]
+        size: ThumbnailSize,
+
+        /// Create the missing thumbnails of every image that matches given selectors
+        #[arg(short = 'a', long = "all", conflicts_with = "identifier")]
+        all: bool,
+
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+
+    /// Render an image in the terminal, with its metadata underneath
+    Preview {
+        /// Metadata ID or path of the image file
+        identifier: OsString,
+
+        /// Size of the preview in cells, as COLUMNSxROWS (e.g. 80x40) [default: fzf preview window or terminal size]
+        #[arg(short = 's', long = "size", value_parser = parse_dimensions)]
+        size: Option<(u32, u32)>,
+
+        /// Graphics protocol to render the image with
+        #[arg(short = 'p', long = "protocol", value_enum, default_value_t = PreviewProtocol::Auto)]
+        protocol: PreviewProtocol,
+
+        /// Only look for the image in the root with this name
+        #[arg(short = 'r', long = "root-name")]
+        root_name: Option<String>,
+    },
+
+    /// Browse images that match given selectors in a terminal UI, to rate, tag and describe them
+    Tui {
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+
+    /// Rate images that have no value for a score yet, one by one
+    Rate {
+        /// Name of the score to rate (e.g. favorite)
+        #[arg(long = "score")]
+        score: String,
+
+        /// Order to go through images in
+        #[arg(short = 'o', long = "order", value_enum, default_value_t = RateOrder::Random)]
+        order: RateOrder,
+
+        /// Graphics protocol to render images with
+        #[arg(long = "protocol", value_enum, default_value_t = PreviewProtocol::Auto)]
+        protocol: PreviewProtocol,
+
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+
+    /// Compare images two at a time to rank them, and write their rank as a score
+    Duel {
+        /// Name of the score to write ranks to (e.g. aesthetic)
+        #[arg(long = "score")]
+        score: String,
+
+        /// Graphics protocol to render images with
+        #[arg(long = "protocol", value_enum, default_value_t = PreviewProtocol::Auto)]
+        protocol: PreviewProtocol,
+
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+
+    /// Rotate wallpapers in the background, driven with `kanumi ctl`
+    Daemon {
+        #[command(flatten)]
+        filters: FilterArgs,
+
+        /// Time between two wallpapers (e.g. 30m, 2h). Default is the one set in configuration
+        #[arg(long = "interval", value_parser = parse_duration)]
+        interval: Option<Duration>,
+    },
+
+    /// Control the running wallpaper daemon
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommands,
+    },
+
+    /// Serve the library over a local HTTP JSON API
+    Serve {
+        /// Address and port to listen on
+        #[arg(short = 'b', long = "bind", default_value = "127.0.0.1:8080")]
+        bind: SocketAddr,
+
+        /// Require clients to send this token
+        #[arg(long = "token", env = TOKEN_VAR, hide_env_values = true)]
+        token: Option<String>,
+    },
+
+    /// Watch the image roots and keep their metadata in sync
+    Watch {
+        /// Only watch the root with this name
+        #[arg(short = 'r', long = "root-name")]
+        root_name: Option<String>,
+
+        /// Wait for this long without new events before updating metadata (e.g. 2s, 1m)
+        #[arg(short = 'd', long = "debounce", default_value = "2s", value_parser = parse_duration)]
+        debounce: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeepStrategy {
+    /// Keep the least recently modified copy
+    Oldest,
+    /// Keep the copy with the shortest path
+    Shortest,
+    /// Keep the copy with the highest resolution
+    Resolution,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DupesAction {
+    /// Only list groups of duplicates
+    List,
+    /// Replace the other copies with hard links to the kept one
+    Hardlink,
+    /// Move the other copies to the trash
+    Trash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PreviewProtocol {
+    /// Guess the protocol supported by the terminal
+    Auto,
+    /// Kitty graphics protocol (kitty, Ghostty, WezTerm)
+    Kitty,
+    /// Sixel graphics (foot, mlterm, iTerm2...)
+    Sixel,
+    /// Unicode half blocks in true colors, for any terminal
+    Blocks,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RateOrder {
+    /// Shuffle images
+    Random,
+    /// Sort images by path
+    Path,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct FilterArgs {
+    /// Filter based on parent directories
+    #[arg(short = 'd', long = "directories")]
+    pub active_directories: Option<Vec<PathBuf>>,
+
+    /// Filter based on score range
+    #[arg(short = 's', long = "scores", value_parser = parse_score_filters)]
+    pub scores: Option<Vec<ScoreFilter>>,
+
+    /// Filter based on width range
+    #[arg(short = 'W', long = "width", value_parser = parse_range)]
+    pub width_range: Option<RangeInclusive<usize>>,
+
+    /// Filter based on height range
+    #[arg(short = 'H', long = "height", value_parser = parse_range)]
+    pub height_range: Option<RangeInclusive<usize>>,
+
+    /// Filter based on tags
+    #[arg(short = 't', long = "tags")]
+    pub tags: Option<Vec<String>>,
+
+    /// Filter based on dominant colors (e.g. `blue,orange`)
+    #[arg(short = 'c', long = "color", value_parser = parse_color, value_delimiter = ',')]
+    pub colors: Option<Vec<Color>>,
+
+    /// Filter based on light or dark theme
+    #[arg(short = 'T', long = "theme", value_parser = parse_theme)]
+    pub theme: Option<ColorTheme>,
+
+    /// Filter images having a color of their palette close to this one (e.g. `#1e1e2e`)
+    #[arg(short = 'n', long = "near-color", value_parser = parse_hex_color)]
+    pub near_colors: Option<Vec<[u8; 3]>>,
+
+    /// Maximum perceptual distance (0-100) between close colors [default: 10]
+    #[arg(long = "distance")]
+    pub distance: Option<f32>,
+
+    /// Rank images by how well their palette matches the colors of this JSON file
+    #[arg(long = "palette")]
+    pub palette_file: Option<PathBuf>,
+
+    /// Maximum perceptual distance (0-100) between the palette of images and `--palette`
+    #[arg(long = "palette-distance", value_name = "DISTANCE")]
+    pub palette_distance: Option<f32>,
+
+    /// Only select images from the root with this name
+    #[arg(short = 'r', long = "root-name")]
+    pub root_name: Option<String>,
+
+    /// Use selectors from the given configuration profile
+    #[arg(short = 'p', long = "profile")]
+    pub profile: Option<String>,
+
+    /// Select the scheduled profile as if it was this local date and time (e.g. `2026-12-24T21:00`)
+    #[arg(long = "at", value_parser = parse_datetime)]
+    pub at: Option<NaiveDateTime>,
+
+    /// Ignore selectors preset from config
+    #[arg(short = 'i', long = "ignore", conflicts_with = "profile")]
+    pub ignore_config: bool,
 }
 
 #[derive(Debug, Args)]
@@ -91,12 +392,91 @@ pub enum ConfigurationCommands {
     /// Print configuration and exit
     Show(ConfigShowFormatArgs),
 
+    /// Print the value of a configuration key
+    Get {
+        /// Dotted path of the key (e.g. `filters.width`)
+        key: String,
+    },
+
+    /// Set the value of a configuration key
+    Set {
+        /// Dotted path of the key (e.g. `root_path`)
+        key: String,
+
+        /// New value, as a TOML value or a plain string
+        value: String,
+    },
+
+    /// Remove a key from the configuration file
+    Unset {
+        /// Dotted path of the key (e.g. `filters.scores`)
+        key: String,
+    },
+
+    /// Open the configuration file in $EDITOR and validate it on save
+    Edit,
+
+    /// List the filter profiles defined in configuration
+    Profiles {
+        /// Output in JSON
+        #[arg(short = 'j', long = "json")]
+        use_json_format: bool,
+    },
+
     /// Generate a default configuration file
     #[command(visible_alias = "gen")]
     Generate {
         /// Only print generated configuration. Does not write to file system
         #[arg(short, long)]
         dry_run: bool,
+
+        /// Overwrite the configuration file if it already exists
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportCommands {
+    /// Write a static HTML gallery with thumbnails, image pages and a JSON index
+    Html {
+        #[command(flatten)]
+        filters: FilterArgs,
+
+        /// Directory to write the gallery to
+        #[arg(short = 'o', long = "out")]
+        out_dir: PathBuf,
+
+        /// Maximum width and height of thumbnails, in pixels
+        #[arg(long = "thumbnail-size", default_value_t = 400)]
+        thumbnail_size: u32,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CtlCommands {
+    /// Apply the next wallpaper
+    Next,
+    /// Apply the previous wallpaper again
+    Prev,
+    /// Stop rotating wallpapers
+    Pause,
+    /// Start rotating wallpapers again
+    Resume,
+    /// Score the current wallpaper
+    Rate {
+        /// Score value
+        value: u8,
+
+        /// Name of the score. Default is the one set in configuration
+        #[arg(short = 's', long = "score")]
+        score: Option<String>,
+    },
+    /// Print the state of the daemon
+    Status {
+        /// Output in JSON
+        #[arg(short = 'j', long = "json")]
+        use_json_format: bool,
     },
 }
 
@@ -114,6 +494,10 @@ pub enum MetadataCommands {
         /// The search query
         query: OsString,
 
+        /// Only search images from the root with this name
+        #[arg(short = 'r', long = "root-name")]
+        root_name: Option<String>,
+
         /// Output in JSON
         #[arg(short = 'j', long = "json")]
         use_json_format: bool,
@@ -123,9 +507,29 @@ pub enum MetadataCommands {
         /// Metadata ID or path of the image file
         identifier: OsString,
 
-        /// Updated metadata string in JSON format
+        /// Fields to change in JSON format (title, description, tags, scores, theme)
         payload: OsString,
     },
+    /// Update the metadata of many images at once, nothing is written if any entry is invalid
+    Import {
+        /// JSON file mapping metadata IDs or image paths to the fields to change, as for `edit`
+        file: PathBuf,
+    },
+    /// Check metadata file for inconsistencies and integrity issues
+    Check {
+        /// Output in JSON
+        #[arg(short = 'j', long = "json")]
+        use_json_format: bool,
+    },
+    /// Extract the dominant colors and theme of images and save them to their metadata
+    Analyze {
+        /// Metadata ID or path of the image file. Default is every image that was not analyzed
+        identifier: Option<OsString>,
+
+        /// Analyze images again, even if they already have colors
+        #[arg(short, long)]
+        force: bool,
+    },
     /// Generate default metadata for a given image
     #[command(visible_alias = "gen")]
     Generate {
@@ -0,0 +1,228 @@
+use anyhow::{bail, Result};
+use log::{debug, info};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    models::{Configuration, ImageMeta, ImageRoot},
+    utils,
+};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum IssueKind {
+    DuplicateId,
+    DuplicatePath,
+    MissingFile,
+    IdMismatch,
+    DimensionsMismatch,
+    ScoreOutOfBounds,
+    UnknownScore,
+    InvalidTag,
+    OutsideRoot,
+    Unreadable,
+}
+
+impl IssueKind {
+    fn label(&self) -> &'static str {
+        match self {
+            IssueKind::DuplicateId => "duplicate ids",
+            IssueKind::DuplicatePath => "duplicate paths",
+            IssueKind::MissingFile => "missing files",
+            IssueKind::IdMismatch => "id mismatches",
+            IssueKind::DimensionsMismatch => "dimensions mismatches",
+            IssueKind::ScoreOutOfBounds => "scores out of bounds",
+            IssueKind::UnknownScore => "unknown scores",
+            IssueKind::InvalidTag => "invalid tags",
+            IssueKind::OutsideRoot => "outside roots",
+            IssueKind::Unreadable => "unreadable files",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MetadataIssue {
+    kind: IssueKind,
+    id: String,
+    path: PathBuf,
+    message: String,
+}
+
+impl MetadataIssue {
+    fn new(kind: IssueKind, meta: &ImageMeta, message: String) -> MetadataIssue {
+        MetadataIssue {
+            kind,
+            id: meta.id.clone(),
+            path: meta.path.clone(),
+            message,
+        }
+    }
+}
+
+pub fn check_metadata(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    metadatas: &[ImageMeta],
+    use_json_format: bool,
+) -> Result<()> {
+    info!("checking {} metadatas...", metadatas.len());
+    let mut issues = vec![];
+
+    issues.extend(find_duplicates(metadatas));
+
+    for meta in metadatas.iter() {
+        issues.extend(check_meta(configuration, roots, meta));
+    }
+
+    debug!("found {} issues", issues.len());
+    match use_json_format {
+        true => {
+            info!("outputting as json");
+            let issues_json = serde_json::to_string(&issues)?;
+            println!("{issues_json}");
+        }
+        false => print_issues(&issues),
+    }
+
+    if !issues.is_empty() {
+        bail!("metadata check failed with {} issues", issues.len());
+    }
+
+    Ok(())
+}
+
+fn find_duplicates(metadatas: &[ImageMeta]) -> Vec<MetadataIssue> {
+    let mut issues = vec![];
+    let mut ids: HashMap<&str, usize> = HashMap::new();
+    let mut paths: HashMap<&Path, usize> = HashMap::new();
+
+    for meta in metadatas.iter() {
+        *ids.entry(&meta.id).or_default() += 1;
+        *paths.entry(&meta.path).or_default() += 1;
+    }
+
+    for meta in metadatas.iter() {
+        let id_count = ids[meta.id.as_str()];
+        if id_count > 1 {
+            let message = format!("id is used by {id_count} entries");
+            issues.push(MetadataIssue::new(IssueKind::DuplicateId, meta, message));
+        }
+
+        let path_count = paths[meta.path.as_path()];
+        if path_count > 1 {
+            let message = format!("path is used by {path_count} entries");
+            issues.push(MetadataIssue::new(IssueKind::DuplicatePath, meta, message));
+        }
+    }
+
+    issues
+}
+
+fn check_meta(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    meta: &ImageMeta,
+) -> Vec<MetadataIssue> {
+    let mut issues = vec![];
+
+    if !roots.iter().any(|root| root.contains(&meta.path)) {
+        let message = String::from("path does not belong to any configured root");
+        issues.push(MetadataIssue::new(IssueKind::OutsideRoot, meta, message));
+    }
+
+    for score in meta.scores.iter() {
+        if let Err(e) = configuration.ensure_score_is_defined(&score.name) {
+            issues.push(MetadataIssue::new(
+                IssueKind::UnknownScore,
+                meta,
+                e.to_string(),
+            ));
+            continue;
+        }
+
+        if let Some(score_range) = configuration.get_score_range(&score.name) {
+            if !score_range.contains(&score.value) {
+                let message = format!(
+                    "score `{}` = {} is not in {}..={}",
+                    score.name,
+                    score.value,
+                    score_range.start(),
+                    score_range.end()
+                );
+                issues.push(MetadataIssue::new(
+                    IssueKind::ScoreOutOfBounds,
+                    meta,
+                    message,
+                ));
+            }
+        }
+    }
+
+    for tag in meta.tags.iter() {
+        if tag.trim().is_empty() {
+            let message = format!("tag is empty or whitespace: {tag:?}");
+            issues.push(MetadataIssue::new(IssueKind::InvalidTag, meta, message));
+        }
+    }
+
+    if !meta.path.exists() {
+        let message = String::from("image file does not exist");
+        issues.push(MetadataIssue::new(IssueKind::MissingFile, meta, message));
+        return issues;
+    }
+
+    match utils::common::compute_blake3_hash(&meta.path) {
+        Ok(hash) if hash != meta.id => {
+            let message = format!("id does not match blake3 hash of file: {hash}");
+            issues.push(MetadataIssue::new(IssueKind::IdMismatch, meta, message));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            let message = format!("failed to hash image file: {e}");
+            issues.push(MetadataIssue::new(IssueKind::Unreadable, meta, message));
+            return issues;
+        }
+    }
+
+    match utils::common::get_image_dims(&meta.path) {
+        Ok((width, height)) if (width, height) != (meta.width, meta.height) => {
+            let message = format!(
+                "stored dimensions {}x{} differ from actual {}x{}",
+                meta.width, meta.height, width, height
+            );
+            issues.push(MetadataIssue::new(
+                IssueKind::DimensionsMismatch,
+                meta,
+                message,
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            let message = format!("failed to get image dimensions: {e}");
+            issues.push(MetadataIssue::new(IssueKind::Unreadable, meta, message));
+        }
+    }
+
+    issues
+}
+
+fn print_issues(issues: &[MetadataIssue]) {
+    let mut kinds: Vec<IssueKind> = vec![];
+    for issue in issues.iter() {
+        if !kinds.contains(&issue.kind) {
+            kinds.push(issue.kind);
+        }
+    }
+
+    for kind in kinds.iter() {
+        let matching: Vec<_> = issues.iter().filter(|issue| issue.kind == *kind).collect();
+        println!("{} {}:", matching.len(), kind.label());
+        for issue in matching.iter() {
+            println!("- {}: {}", issue.path.display(), issue.message);
+        }
+        println!();
+    }
+}
@@ -1,31 +1,67 @@
-use anyhow::Result;
-use log::info;
-use std::path::Path;
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use log::{debug, info, warn};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    process,
+};
+use toml_edit::{DocumentMut, InlineTable, Item, Value};
 
 use super::ConfigurationCommands;
-use crate::{models::Configuration, utils};
+use crate::{
+    models::{Configuration, ConfigurationOverrides},
+    utils,
+};
 
 pub fn handle_config_command(
     command: ConfigurationCommands,
-    configuration: &Configuration,
+    config_path: &Path,
+    overrides: &ConfigurationOverrides,
 ) -> Result<()> {
     match command {
         ConfigurationCommands::Show(display_format) => {
-            let config_path = utils::common::get_config_file()?;
+            let configuration = utils::common::load_config(config_path, overrides)?;
 
             if display_format.json {
-                show_config_as_json(configuration)
+                show_config_as_json(&configuration)
             } else if display_format.toml {
-                show_config_as_toml(configuration, &config_path)
+                show_config_as_toml(&configuration, config_path)
             } else {
-                show_config_with_default_formatter(configuration, &config_path)
+                show_config_with_default_formatter(&configuration, config_path)
             }
         }
-        ConfigurationCommands::Generate { dry_run: _ } => {
+        ConfigurationCommands::Get { key } => {
+            let configuration = utils::common::load_config(config_path, overrides)?;
+            get_config_value(&configuration, &key)
+        }
+        ConfigurationCommands::Set { key, value } => set_config_value(config_path, &key, &value),
+        ConfigurationCommands::Unset { key } => unset_config_value(config_path, &key),
+        ConfigurationCommands::Edit => edit_config(config_path),
+        ConfigurationCommands::Profiles { use_json_format } => {
+            let configuration = utils::common::load_config(config_path, overrides)?;
+            show_profiles(&configuration, use_json_format)
+        }
+        ConfigurationCommands::Generate { dry_run, force } => {
             info!("generating default config...");
             let default_config = Configuration::create_default();
             let toml = default_config.to_toml_str()?;
-            print!("{}", toml);
+
+            if dry_run {
+                print!("{}", toml);
+                return Ok(());
+            }
+
+            if config_path.exists() && !force {
+                bail!(
+                    "config file already exists: {}, use --force to overwrite it",
+                    config_path.display()
+                );
+            }
+
+            utils::common::create_config_file(config_path)?;
+            println!("{}", config_path.display());
             Ok(())
         }
     }
@@ -46,9 +82,231 @@ fn show_config_as_toml(configuration: &Configuration, config_path: &Path) -> Res
     Ok(())
 }
 
+/// Print the configuration as TOML, annotated with where each value comes from
 fn show_config_with_default_formatter(
     configuration: &Configuration,
     config_path: &Path,
 ) -> Result<()> {
-    show_config_as_toml(configuration, config_path)
+    let banner = utils::common::create_banner(&config_path.display().to_string());
+    println!("{banner}");
+
+    let mut document = configuration.to_toml_str()?.parse::<DocumentMut>()?;
+    for (key, source) in configuration.sources.iter() {
+        match document.get_mut(key) {
+            Some(Item::Value(value)) => {
+                value.decor_mut().set_suffix(format!(" # {source}"));
+            }
+            Some(Item::Table(table)) => {
+                table.set_implicit(false);
+                table.decor_mut().set_prefix(format!("\n# {source}\n"));
+            }
+            _ => {}
+        }
+    }
+
+    println!("{document}");
+    Ok(())
+}
+
+fn show_profiles(configuration: &Configuration, use_json_format: bool) -> Result<()> {
+    match use_json_format {
+        true => {
+            let profiles_json = serde_json::to_string(&configuration.profiles)?;
+            println!("{profiles_json}");
+        }
+        false => {
+            let now = Local::now().naive_local();
+            let active_profile = configuration.resolve_profile_name(None, now)?;
+
+            for name in configuration.profiles.keys() {
+                let mut labels = vec![];
+                if configuration.default_profile.as_ref() == Some(name) {
+                    labels.push("default");
+                }
+                if active_profile == Some(name.as_str()) {
+                    labels.push("active");
+                }
+
+                match labels.is_empty() {
+                    true => println!("{name}"),
+                    false => println!("{name} ({})", labels.join(", ")),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get_config_value(configuration: &Configuration, key: &str) -> Result<()> {
+    let mut value = toml::Value::try_from(configuration)?;
+
+    for part in key.split('.') {
+        value = match value.get(part) {
+            Some(value) => value.clone(),
+            None => bail!("configuration key is not set: {key}"),
+        };
+    }
+
+    match value {
+        toml::Value::String(value) => println!("{value}"),
+        toml::Value::Table(table) => print!("{}", toml::to_string(&table)?),
+        value => println!("{value}"),
+    }
+
+    Ok(())
+}
+
+fn set_config_value(config_path: &Path, key: &str, value: &str) -> Result<()> {
+    let mut document = load_config_document(config_path)?;
+    let (table_keys, last_key) = split_key(key)?;
+
+    let mut table = document.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for table_key in table_keys.iter() {
+        table = table
+            .entry(table_key)
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .context(format!("configuration key is not a table: {table_key}"))?;
+    }
+
+    let mut value = parse_toml_value(value);
+    debug!("setting `{key}` to: {value}");
+
+    match table.get_mut(last_key) {
+        Some(Item::Value(existing)) => {
+            // Keep comments and spacing around the previous value
+            *value.decor_mut() = existing.decor().clone();
+            *existing = value;
+        }
+        _ => {
+            table.insert(last_key, Item::Value(value));
+        }
+    }
+
+    save_config_document(config_path, &document)
+}
+
+fn unset_config_value(config_path: &Path, key: &str) -> Result<()> {
+    let mut document = load_config_document(config_path)?;
+    let (table_keys, last_key) = split_key(key)?;
+
+    let mut table = document.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for table_key in table_keys.iter() {
+        table = match table.get_mut(table_key).and_then(Item::as_table_like_mut) {
+            Some(table) => table,
+            None => bail!("configuration key is not set: {key}"),
+        };
+    }
+
+    if table.remove(last_key).is_none() {
+        bail!("configuration key is not set: {key}");
+    }
+
+    save_config_document(config_path, &document)
+}
+
+fn edit_config(config_path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+
+    let mut editor_parts = editor.split_whitespace();
+    let editor_cmd = editor_parts
+        .next()
+        .context("editor command should not be empty")?;
+    let editor_args: Vec<_> = editor_parts.collect();
+
+    let edit_path = env::temp_dir().join(format!("kanumi-config-{}.toml", process::id()));
+    fs::copy(config_path, &edit_path)?;
+
+    loop {
+        info!("opening {} with: {}", edit_path.display(), editor);
+        let status = process::Command::new(editor_cmd)
+            .args(&editor_args)
+            .arg(&edit_path)
+            .status()
+            .context(format!("failed to run editor: {editor}"))?;
+
+        if !status.success() {
+            fs::remove_file(&edit_path)?;
+            bail!("editor exited with: {status}");
+        }
+
+        let content = fs::read_to_string(&edit_path)?;
+        match utils::common::parse_config(&content) {
+            Ok(_) => {
+                fs::write(config_path, content)?;
+                fs::remove_file(&edit_path)?;
+                info!("configuration saved");
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("invalid configuration: {e}");
+                eprint!("Invalid configuration: {e}\nEdit again? [Y/n] ");
+                io::stderr().flush()?;
+
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if answer.trim().eq_ignore_ascii_case("n") {
+                    fs::remove_file(&edit_path)?;
+                    bail!("configuration was not saved");
+                }
+            }
+        }
+    }
+}
+
+fn load_config_document(config_path: &Path) -> Result<DocumentMut> {
+    let content = fs::read_to_string(config_path)?;
+    Ok(content.parse::<DocumentMut>()?)
+}
+
+/// Validate the edited configuration and write it back, preserving comments and formatting
+fn save_config_document(config_path: &Path, document: &DocumentMut) -> Result<()> {
+    let content = document.to_string();
+    utils::common::parse_config(&content).context("refusing to save invalid configuration")?;
+
+    fs::write(config_path, content)?;
+    info!("configuration saved to: {}", config_path.display());
+    Ok(())
+}
+
+fn split_key(key: &str) -> Result<(Vec<&str>, &str)> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last_key = parts
+        .pop()
+        .context("configuration key should not be empty")?;
+
+    if parts.iter().chain([&last_key]).any(|part| part.is_empty()) {
+        bail!("invalid configuration key: `{key}`");
+    }
+
+    Ok((parts, last_key))
+}
+
+/// Parse the input as a TOML value, then as a range (e.g. `0..100`), falling back to a plain string
+fn parse_toml_value(input: &str) -> Value {
+    match input.parse::<Value>() {
+        Ok(value) => value.decorated(" ", ""),
+        Err(_) => match parse_range_value(input) {
+            Some(range) => Value::InlineTable(range).decorated(" ", ""),
+            None => Value::from(input),
+        },
+    }
+}
+
+/// Parse `N..O` or `..O` as the `{ start = N, end = O }` table that ranges are stored as
+fn parse_range_value(input: &str) -> Option<InlineTable> {
+    let (start, end) = input.split_once("..")?;
+    let start = match start.trim() {
+        "" => 0,
+        start => start.parse::<i64>().ok()?,
+    };
+    let end = end.trim().parse::<i64>().ok()?;
+
+    let mut range = InlineTable::new();
+    range.insert("start", Value::from(start));
+    range.insert("end", Value::from(end));
+    Some(range)
 }
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use log::info;
+
+use super::{daemon, CtlCommands};
+use crate::models::{Configuration, DaemonRequest};
+
+pub fn handle_ctl_command(command: CtlCommands, configuration: &Configuration) -> Result<()> {
+    let request = match &command {
+        CtlCommands::Next => DaemonRequest::Next,
+        CtlCommands::Prev => DaemonRequest::Prev,
+        CtlCommands::Pause => DaemonRequest::Pause,
+        CtlCommands::Resume => DaemonRequest::Resume,
+        CtlCommands::Rate { value, score } => DaemonRequest::Rate {
+            score: score.clone(),
+            value: *value,
+        },
+        CtlCommands::Status { .. } => DaemonRequest::Status,
+    };
+
+    let status = daemon::send_request(configuration, &request)?;
+    match command {
+        CtlCommands::Next | CtlCommands::Prev => {
+            if let Some(current) = &status.current {
+                println!("{}", current.display());
+            }
+        }
+        CtlCommands::Status { use_json_format } => match use_json_format {
+            true => {
+                info!("outputting as json");
+                println!("{}", serde_json::to_string(&status)?);
+            }
+            false => {
+                let current = status
+                    .current
+                    .as_ref()
+                    .map(|path| path.display().to_string());
+                let next_change = status
+                    .next_change
+                    .map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string());
+
+                println!("current: {}", current.as_deref().unwrap_or("none"));
+                println!("profile: {}", status.profile.as_deref().unwrap_or("none"));
+                println!("images: {}", status.images);
+                println!("paused: {}", if status.paused { "yes" } else { "no" });
+                println!("next change: {}", next_change.as_deref().unwrap_or("none"));
+            }
+        },
+        CtlCommands::Pause | CtlCommands::Resume | CtlCommands::Rate { .. } => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,384 @@
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{Duration, Local};
+use log::{debug, error, info, warn};
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{self, Instant},
+};
+
+use super::{args::FilterArgs, list};
+use crate::{
+    models::{Configuration, DaemonRequest, DaemonResponse, DaemonStatus, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Request received on the control socket, with the channel to send the answer to
+type Message = (DaemonRequest, Sender<DaemonResponse>);
+
+/// Time given to a client to send its request, so that a silent client does not block the others
+const CLIENT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+struct Daemon<'a> {
+    configuration: &'a Configuration,
+    roots: &'a [ImageRoot],
+    filters: &'a FilterArgs,
+    interval: Duration,
+    /// Metadatas of every image of the roots, loaded once
+    library: Vec<ImageMeta>,
+    /// Images matching the filters of the profile active when the wallpaper last changed
+    candidates: Vec<ImageMeta>,
+    /// Profile the candidates were selected with
+    profile: Option<String>,
+    /// Applied wallpapers, oldest first
+    history: Vec<PathBuf>,
+    /// Index of the current wallpaper in history
+    position: usize,
+    paused: bool,
+    next_change: Instant,
+}
+
+pub fn run_daemon(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
+    interval: Option<Duration>,
+) -> Result<()> {
+    ensure!(
+        !configuration.daemon.setter.is_empty(),
+        "no wallpaper setter configured, set `daemon.setter` (e.g. [\"swww\", \"img\", \"{{path}}\"])"
+    );
+
+    let interval = match interval {
+        Some(interval) => interval,
+        None => configuration.daemon.get_interval()?,
+    };
+
+    let socket_path = utils::common::get_socket_path(configuration)?;
+    let listener = bind_socket(&socket_path)?;
+    info!("listening on: {}", socket_path.display());
+
+    let (sender, receiver) = mpsc::channel::<Message>();
+    thread::spawn(move || accept_connections(listener, sender));
+
+    let library = utils::common::load_root_metas(roots)?;
+    info!("loaded {} metadatas", library.len());
+
+    let mut daemon = Daemon {
+        configuration,
+        roots,
+        filters,
+        interval,
+        library,
+        candidates: vec![],
+        profile: None,
+        history: vec![],
+        position: 0,
+        paused: false,
+        next_change: Instant::now(),
+    };
+
+    if let Err(e) = daemon.next() {
+        error!("failed to apply wallpaper: {e}");
+    }
+
+    loop {
+        let message = match daemon.paused {
+            true => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            false => {
+                let timeout = daemon.next_change.saturating_duration_since(Instant::now());
+                receiver.recv_timeout(timeout)
+            }
+        };
+
+        match message {
+            Ok((request, reply)) => {
+                debug!("received request: {:?}", request);
+                let response = match daemon.handle_request(request) {
+                    Ok(status) => DaemonResponse::Status(status),
+                    Err(e) => DaemonResponse::Error(e.to_string()),
+                };
+
+                // The client may have hung up already
+                let _ = reply.send(response);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = daemon.next() {
+                    error!("failed to apply wallpaper: {e}");
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => bail!("control socket closed"),
+        }
+    }
+}
+
+/// Bind the control socket, replacing the socket file left by a daemon that is not running anymore
+fn bind_socket(socket_path: &Path) -> Result<UnixListener> {
+    if socket_path.exists() {
+        ensure!(
+            UnixStream::connect(socket_path).is_err(),
+            "daemon is already running on: {}",
+            socket_path.display()
+        );
+
+        warn!("removing stale socket: {}", socket_path.display());
+        fs::remove_file(socket_path)?;
+    }
+
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    UnixListener::bind(socket_path)
+        .with_context(|| format!("failed to bind socket: {}", socket_path.display()))
+}
+
+fn accept_connections(listener: UnixListener, sender: Sender<Message>) {
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| handle_connection(stream, &sender));
+
+        if let Err(e) = result {
+            warn!("failed to handle connection: {e}");
+        }
+    }
+}
+
+fn handle_connection(stream: UnixStream, sender: &Sender<Message>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = String::new();
+    match BufReader::new(&stream).read_line(&mut line) {
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            bail!("no request received within {}s", CLIENT_TIMEOUT.as_secs())
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let response = match serde_json::from_str::<DaemonRequest>(&line) {
+        Ok(request) => {
+            let (reply_sender, reply_receiver) = mpsc::channel();
+            sender.send((request, reply_sender))?;
+            reply_receiver.recv()?
+        }
+        Err(e) => DaemonResponse::Error(format!("invalid request: {e}")),
+    };
+
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+impl Daemon<'_> {
+    fn handle_request(&mut self, request: DaemonRequest) -> Result<DaemonStatus> {
+        match request {
+            DaemonRequest::Next => self.next()?,
+            DaemonRequest::Prev => self.prev()?,
+            DaemonRequest::Pause => {
+                info!("paused");
+                self.paused = true;
+            }
+            DaemonRequest::Resume => {
+                info!("resumed");
+                self.paused = false;
+                self.schedule_next_change()?;
+            }
+            DaemonRequest::Rate { score, value } => self.rate(score, value)?,
+            DaemonRequest::Status => {}
+        }
+
+        self.get_status()
+    }
+
+    fn get_status(&self) -> Result<DaemonStatus> {
+        let now = Local::now();
+        let next_change = match self.paused {
+            true => None,
+            false => {
+                let remaining = self.next_change.saturating_duration_since(Instant::now());
+                Some(now + Duration::from_std(remaining)?)
+            }
+        };
+
+        Ok(DaemonStatus {
+            current: self.history.get(self.position).cloned(),
+            paused: self.paused,
+            images: self.candidates.len(),
+            profile: self.profile.clone(),
+            next_change,
+        })
+    }
+
+    /// Metadatas of the images that can be picked, with the filters of the profile active now,
+    /// along with the name of that profile
+    fn get_candidates(&self) -> Result<(Vec<ImageMeta>, Option<String>)> {
+        // The profile is resolved once, so that it is the one the filters come from
+        let at = self
+            .filters
+            .at
+            .unwrap_or_else(|| Local::now().naive_local());
+        let profile = match self.filters.ignore_config {
+            true => None,
+            false => self
+                .configuration
+                .resolve_profile_name(self.filters.profile.as_deref(), at)?
+                .map(str::to_owned),
+        };
+
+        let filters = FilterArgs {
+            at: Some(at),
+            ..self.filters.clone()
+        };
+        let filters = list::resolve_filters(filters, self.configuration)?;
+        let mut candidates = list::filter_metas(self.roots, self.library.clone(), &filters)?;
+
+        // Images may have been moved or deleted since the library was loaded
+        candidates.retain(|meta| meta.path.exists());
+        Ok((candidates, profile))
+    }
+
+    /// Go forward in history, or apply a random image when at the end of it
+    fn next(&mut self) -> Result<()> {
+        self.schedule_next_change()?;
+        (self.candidates, self.profile) = self.get_candidates()?;
+
+        if self.position + 1 < self.history.len() {
+            self.position += 1;
+            return self.apply();
+        }
+
+        let current = self.history.get(self.position);
+        let mut choices: Vec<&ImageMeta> = self
+            .candidates
+            .iter()
+            .filter(|meta| Some(&meta.path) != current)
+            .collect();
+
+        // Keep the same wallpaper when it is the only one matching
+        if choices.is_empty() {
+            choices = self.candidates.iter().collect();
+        }
+
+        ensure!(!choices.is_empty(), "no image matches the filters");
+        let choice = choices[fastrand::usize(..choices.len())].path.clone();
+
+        self.history.push(choice);
+        self.position = self.history.len() - 1;
+        self.apply()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure!(self.position > 0, "no previous wallpaper");
+
+        self.schedule_next_change()?;
+        self.position -= 1;
+        self.apply()
+    }
+
+    fn schedule_next_change(&mut self) -> Result<()> {
+        self.next_change = Instant::now() + self.interval.to_std()?;
+        Ok(())
+    }
+
+    /// Run the configured setter on the current wallpaper
+    fn apply(&self) -> Result<()> {
+        let path = self
+            .history
+            .get(self.position)
+            .context("no current wallpaper")?;
+
+        let path_str = path.to_string_lossy();
+        let setter = &self.configuration.daemon.setter;
+        let args: Vec<String> = setter[1..]
+            .iter()
+            .map(|arg| arg.replace("{path}", &path_str))
+            .collect();
+
+        debug!("running setter: {} {:?}", setter[0], args);
+        let output = Command::new(&setter[0])
+            .args(&args)
+            .output()
+            .with_context(|| format!("failed to run setter: {}", setter[0]))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("setter failed with {}: {}", output.status, stderr.trim());
+        }
+
+        info!("applied wallpaper: {}", path.display());
+        Ok(())
+    }
+
+    /// Set a score of the current wallpaper, in memory and in its metadata file
+    fn rate(&mut self, score: Option<String>, value: u8) -> Result<()> {
+        let score = score.unwrap_or_else(|| self.configuration.daemon.score.clone());
+        self.configuration.validate_score(&score, value)?;
+
+        let path = self
+            .history
+            .get(self.position)
+            .context("no current wallpaper")?;
+
+        let meta = self
+            .library
+            .iter_mut()
+            .find(|meta| meta.path == *path)
+            .context(format!("no metadata for: {}", path.display()))?;
+
+        meta.set_score(&score, value);
+        let root = self
+            .roots
+            .iter()
+            .find(|root| root.contains(path))
+            .context(format!("no root contains: {}", path.display()))?;
+
+        let (id, path) = (meta.id.clone(), meta.path.clone());
+        utils::common::update_image_metas(&root.metadata_path, |metas| {
+            match metas
+                .iter_mut()
+                .find(|meta| meta.id == id && meta.path == path)
+            {
+                Some(meta) => meta.set_score(&score, value),
+                None => warn!("metadata was removed from file: {}", path.display()),
+            }
+
+            Ok(())
+        })?;
+
+        info!("rated {}: {} = {}", path.display(), score, value);
+        Ok(())
+    }
+}
+
+/// Send a request to the running daemon and wait for its answer
+pub fn send_request(
+    configuration: &Configuration,
+    request: &DaemonRequest,
+) -> Result<DaemonStatus> {
+    let socket_path = utils::common::get_socket_path(configuration)?;
+    let stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            bail!("daemon is not running on: {}", socket_path.display())
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    match serde_json::from_str(&line).context("invalid response from daemon")? {
+        DaemonResponse::Status(status) => Ok(status),
+        DaemonResponse::Error(message) => bail!("{message}"),
+    }
+}
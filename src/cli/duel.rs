@@ -0,0 +1,398 @@
+use anyhow::{ensure, Context, Result};
+use log::{debug, info};
+use ratatui::crossterm::{cursor, event::KeyCode, queue, terminal};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, IsTerminal, Write},
+    ops::RangeInclusive,
+};
+
+use super::{args::FilterArgs, list, preview, PreviewProtocol};
+use crate::{
+    models::{Configuration, DuelRating, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Lines around the images: the header, the labels, the prompt and a message
+const RESERVED_ROWS: u32 = 4;
+
+/// Ratings of both images before a duel, to undo it
+type Snapshot = [(String, DuelRating); 2];
+
+/// Show two images at a time and ask which one is better, to rank images with Glicko ratings
+/// written back as a score
+pub fn run_duels(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
+    score: &str,
+    protocol: PreviewProtocol,
+) -> Result<()> {
+    ensure!(!score.is_empty(), "score name should not be empty");
+    configuration.ensure_score_is_defined(score)?;
+    ensure!(
+        io::stdin().is_terminal() && io::stdout().is_terminal(),
+        "duels require an interactive terminal"
+    );
+
+    let library = utils::common::load_root_metas(roots)?;
+    let mut candidates = list::filter_metas(roots, library.clone(), filters)?;
+
+    // Copies of the same image share their rating
+    let mut seen_ids = HashSet::new();
+    candidates.retain(|meta| seen_ids.insert(meta.id.clone()));
+    ensure!(
+        candidates.len() >= 2,
+        "at least 2 images are needed for duels, found: {}",
+        candidates.len()
+    );
+
+    let mut all_ratings = utils::common::load_duel_ratings()?;
+    all_ratings.scores.entry(score.to_owned()).or_default();
+
+    let protocol = preview::resolve_protocol(protocol);
+    let mut history: Vec<Snapshot> = vec![];
+    let mut last_pair = None;
+    let mut message = None;
+    loop {
+        let ratings = all_ratings
+            .scores
+            .get_mut(score)
+            .context("ratings of the score should exist")?;
+
+        let (left, right) = pick_pair(&candidates, ratings, last_pair.as_ref());
+        let (left, right) = (&candidates[left], &candidates[right]);
+        let left_rating = ratings.get(&left.id).copied().unwrap_or_default();
+        let right_rating = ratings.get(&right.id).copied().unwrap_or_default();
+        show_duel(
+            [(left, &left_rating), (right, &right_rating)],
+            history.len(),
+            protocol,
+            message.take(),
+        )?;
+
+        let left_score = match preview::read_key()? {
+            KeyCode::Char('1' | 'h') | KeyCode::Left => 1.0,
+            KeyCode::Char('2' | 'l') | KeyCode::Right => 0.0,
+            KeyCode::Char('=') => 0.5,
+            KeyCode::Char(' ' | 's') => {
+                last_pair = Some((left.id.clone(), right.id.clone()));
+                continue;
+            }
+            KeyCode::Char('u') | KeyCode::Backspace => {
+                match history.pop() {
+                    Some(snapshot) => {
+                        for (id, rating) in snapshot {
+                            ratings.insert(id, rating);
+                        }
+                        utils::common::save_duel_ratings(&all_ratings)?;
+                    }
+                    None => message = Some(String::from("nothing to undo")),
+                }
+                continue;
+            }
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            _ => {
+                message = Some(String::from("unknown key"));
+                continue;
+            }
+        };
+
+        history.push([
+            (left.id.clone(), left_rating),
+            (right.id.clone(), right_rating),
+        ]);
+        ratings.insert(
+            left.id.clone(),
+            left_rating.update(&right_rating, left_score),
+        );
+        ratings.insert(
+            right.id.clone(),
+            right_rating.update(&left_rating, 1.0 - left_score),
+        );
+
+        last_pair = Some((left.id.clone(), right.id.clone()));
+        debug!("{} vs {}: {}", left.id, right.id, left_score);
+
+        // Save after every duel, so that none is lost if the terminal is closed
+        utils::common::save_duel_ratings(&all_ratings)?;
+    }
+
+    preview::clear_screen(protocol)?;
+    let duels = history.len();
+    let ratings = all_ratings
+        .scores
+        .get(score)
+        .context("ratings of the score should exist")?;
+
+    let range = configuration.get_score_range(score).unwrap_or(0..=u8::MAX);
+    let values = map_ratings_to_values(ratings, &library, range);
+    let written = save_scores(roots, score, &values)?;
+    println!("{duels} duels, wrote `{score}` score of {written} images");
+    Ok(())
+}
+
+/// Pick the pair of images whose duel is expected to teach the most: the image with the most
+/// uncertain rating, against the opponent that reduces the variance of both ratings the most
+fn pick_pair(
+    candidates: &[ImageMeta],
+    ratings: &HashMap<String, DuelRating>,
+    last_pair: Option<&(String, String)>,
+) -> (usize, usize) {
+    let get_rating = |index: usize| {
+        ratings
+            .get(&candidates[index].id)
+            .copied()
+            .unwrap_or_default()
+    };
+
+    // Shuffled so that ties are broken at random
+    let mut indexes: Vec<usize> = (0..candidates.len()).collect();
+    fastrand::shuffle(&mut indexes);
+
+    let is_last_pair = |a: usize, b: usize| {
+        last_pair.is_some_and(|(left, right)| {
+            let ids = (&candidates[a].id, &candidates[b].id);
+            ids == (left, right) || ids == (right, left)
+        })
+    };
+
+    let first = indexes
+        .iter()
+        .copied()
+        .max_by(|a, b| {
+            get_rating(*a)
+                .deviation
+                .total_cmp(&get_rating(*b).deviation)
+        })
+        .unwrap_or_default();
+
+    let first_rating = get_rating(first);
+    let get_gain = |index: usize| {
+        let rating = get_rating(index);
+        first_rating.get_variance_reduction(&rating) + rating.get_variance_reduction(&first_rating)
+    };
+
+    let second = indexes
+        .iter()
+        .copied()
+        .filter(|index| *index != first && (candidates.len() == 2 || !is_last_pair(first, *index)))
+        .max_by(|a, b| get_gain(*a).total_cmp(&get_gain(*b)))
+        .unwrap_or_default();
+
+    // The most uncertain image is not always shown on the same side
+    match fastrand::bool() {
+        true => (first, second),
+        false => (second, first),
+    }
+}
+
+fn show_duel(
+    contenders: [(&ImageMeta, &DuelRating); 2],
+    duels: usize,
+    protocol: PreviewProtocol,
+    message: Option<String>,
+) -> Result<()> {
+    preview::clear_screen(protocol)?;
+
+    let (columns, rows) = terminal::size()?;
+    let half_columns = columns / 2;
+    let image_rows = u32::from(rows).saturating_sub(RESERVED_ROWS).max(1);
+    let label_row = 1 + image_rows as u16;
+
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "duel {}: which one is better?", duels + 1)?;
+
+    for (side, (meta, rating)) in contenders.iter().enumerate() {
+        let left = side as u16 * half_columns;
+        let image_columns = u32::from(half_columns.saturating_sub(1)).max(1);
+        match preview::render_image(&meta.path, image_columns, image_rows, protocol) {
+            Ok(output) => {
+                for (row, line) in output.lines().enumerate() {
+                    queue!(stdout, cursor::MoveTo(left, 1 + row as u16))?;
+                    stdout.write_all(line.as_bytes())?;
+                }
+            }
+            Err(e) => {
+                queue!(stdout, cursor::MoveTo(left, 1))?;
+                write!(stdout, "failed to render image: {e}")?;
+            }
+        }
+
+        let label = format!(
+            "{}: {} ({:.0} ± {:.0})",
+            side + 1,
+            meta.title,
+            rating.rating,
+            rating.deviation
+        );
+        let label: String = label.chars().take(image_columns as usize).collect();
+        queue!(stdout, cursor::MoveTo(left, label_row))?;
+        write!(stdout, "{label}")?;
+    }
+
+    queue!(stdout, cursor::MoveTo(0, label_row + 1))?;
+    if let Some(message) = message {
+        write!(stdout, "{message}")?;
+    }
+
+    queue!(stdout, cursor::MoveTo(0, label_row + 2))?;
+    write!(
+        stdout,
+        "1 left is better, 2 right is better, = draw, space skip, u undo, q quit "
+    )?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Spread the ratings of images that had duels over the score range, by percentile rank
+fn map_ratings_to_values(
+    ratings: &HashMap<String, DuelRating>,
+    library: &[ImageMeta],
+    range: RangeInclusive<u8>,
+) -> HashMap<String, u8> {
+    let library_ids: HashSet<&str> = library.iter().map(|meta| meta.id.as_str()).collect();
+    let rated: Vec<(&String, f64)> = ratings
+        .iter()
+        .filter(|(id, rating)| rating.duels > 0 && library_ids.contains(id.as_str()))
+        .map(|(id, rating)| (id, rating.rating))
+        .collect();
+
+    let (start, end) = (f64::from(*range.start()), f64::from(*range.end()));
+    let mut values = HashMap::new();
+    for (id, rating) in rated.iter() {
+        let lower = rated.iter().filter(|(_, other)| other < rating).count();
+        let equal = rated.iter().filter(|(_, other)| other == rating).count();
+
+        // Images with the same rating get the same value, in the middle of their ranks
+        let percentile = match rated.len() {
+            1 => 0.5,
+            count => (lower as f64 + (equal - 1) as f64 / 2.0) / (count - 1) as f64,
+        };
+
+        let value = start + (percentile * (end - start)).round();
+        values.insert((*id).clone(), value as u8);
+    }
+
+    values
+}
+
+/// Write the values to the metadata of the images, returning how many images were updated
+fn save_scores(roots: &[ImageRoot], score: &str, values: &HashMap<String, u8>) -> Result<usize> {
+    if values.is_empty() {
+        return Ok(0);
+    }
+
+    let mut written = 0;
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            for meta in metas.iter_mut() {
+                if let Some(value) = values.get(&meta.id) {
+                    meta.set_score(score, *value);
+                    written += 1;
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    info!("wrote {} scores of {} images", score, written);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library(ids: &[&str]) -> Vec<ImageMeta> {
+        ids.iter()
+            .map(|id| {
+                serde_json::from_value(serde_json::json!({
+                    "id": id,
+                    "path": format!("/images/{id}.png"),
+                    "title": "",
+                    "description": "",
+                    "width": 1,
+                    "height": 1,
+                    "scores": [],
+                    "tags": [],
+                    "colors": [],
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn ratings(ratings: &[(&str, f64)]) -> HashMap<String, DuelRating> {
+        ratings
+            .iter()
+            .map(|(id, rating)| {
+                let rating = DuelRating {
+                    rating: *rating,
+                    duels: 1,
+                    ..DuelRating::default()
+                };
+                (id.to_string(), rating)
+            })
+            .collect()
+    }
+
+    fn values(values: &[(&str, u8)]) -> HashMap<String, u8> {
+        values
+            .iter()
+            .map(|(id, value)| (id.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn spreads_ratings_over_the_range() {
+        let ratings = ratings(&[
+            ("a", 1200.0),
+            ("b", 1450.0),
+            ("c", 1500.0),
+            ("d", 1510.0),
+            ("e", 1900.0),
+        ]);
+        let library = library(&["a", "b", "c", "d", "e"]);
+
+        assert_eq!(
+            map_ratings_to_values(&ratings, &library, 1..=5),
+            values(&[("a", 1), ("b", 2), ("c", 3), ("d", 4), ("e", 5)])
+        );
+    }
+
+    #[test]
+    fn gives_tied_ratings_the_same_value() {
+        let ratings = ratings(&[("a", 1400.0), ("b", 1500.0), ("c", 1500.0), ("d", 1600.0)]);
+        let library = library(&["a", "b", "c", "d"]);
+
+        assert_eq!(
+            map_ratings_to_values(&ratings, &library, 0..=9),
+            values(&[("a", 0), ("b", 5), ("c", 5), ("d", 9)])
+        );
+    }
+
+    #[test]
+    fn puts_single_rated_image_in_the_middle() {
+        let ratings = ratings(&[("a", 1800.0)]);
+        let library = library(&["a"]);
+
+        assert_eq!(
+            map_ratings_to_values(&ratings, &library, 1..=5),
+            values(&[("a", 3)])
+        );
+    }
+
+    #[test]
+    fn ignores_images_without_duels_or_metadata() {
+        let mut ratings = ratings(&[("a", 1400.0), ("b", 1600.0), ("gone", 1700.0)]);
+        ratings.insert(String::from("new"), DuelRating::default());
+        let library = library(&["a", "b", "new"]);
+
+        assert_eq!(
+            map_ratings_to_values(&ratings, &library, 0..=10),
+            values(&[("a", 0), ("b", 10)])
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{similar::PerceptualImage, DupesAction, KeepStrategy};
+use crate::{
+    models::{Configuration, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Byte-identical or visually similar images, with the copy to keep separated from the others
+#[derive(Debug, Serialize)]
+struct DuplicateGroup {
+    /// blake3 hash of the copies, or perceptual hash of one of them for similar images
+    id: String,
+    keep: PathBuf,
+    duplicates: Vec<PathBuf>,
+}
+
+pub fn handle_dupes(
+    roots: &[ImageRoot],
+    configuration: &Configuration,
+    perceptual_threshold: Option<u32>,
+    keep: Option<KeepStrategy>,
+    action: DupesAction,
+    dry_run: bool,
+    use_json_format: bool,
+) -> Result<()> {
+    if perceptual_threshold.is_some() && action == DupesAction::Hardlink {
+        bail!("similar images are not identical and cannot be hard linked");
+    }
+
+    let mut perceptual_hashes: HashMap<PathBuf, u64> = HashMap::new();
+    let (identical_groups, keep) = match perceptual_threshold {
+        Some(threshold) => {
+            info!("looking for similar images...");
+            let images = super::similar::load_perceptual_images(roots, &configuration.walker)?;
+            let groups = group_similar_images(&images, threshold);
+            for image in images.into_iter() {
+                perceptual_hashes.insert(image.path, image.hash);
+            }
+            (groups, keep.unwrap_or(KeepStrategy::Resolution))
+        }
+        None => {
+            info!("looking for duplicated images...");
+            let mut images = vec![];
+            let mut seen_images = HashSet::new();
+            for root in roots.iter() {
+                info!("about to walk through {}", root.path.display());
+                for image_path in utils::common::get_root_images(root, &configuration.walker)? {
+                    if seen_images.insert(image_path.clone()) {
+                        images.push(image_path);
+                    }
+                }
+            }
+
+            // Stored ids may be outdated, and files are only deleted when their content is identical
+            let groups = utils::common::find_identical_files(&images, &HashMap::new())?;
+            (groups, keep.unwrap_or(KeepStrategy::Oldest))
+        }
+    };
+
+    let mut groups = vec![];
+    for (id, paths) in identical_groups {
+        let mut group = split_group(id, paths, keep)?;
+
+        // Similar images are grouped transitively, so some of them can be far from the kept one
+        if let Some(threshold) = perceptual_threshold {
+            let kept_hash = perceptual_hashes[&group.keep];
+            group.duplicates.retain(|path| {
+                utils::common::get_hash_distance(kept_hash, perceptual_hashes[path]) <= threshold
+            });
+            if group.duplicates.is_empty() {
+                continue;
+            }
+        }
+
+        groups.push(group);
+    }
+    debug!("found {} groups of duplicates", groups.len());
+
+    let mut failed = vec![];
+    if action != DupesAction::List && !dry_run {
+        failed = apply_action(configuration, roots, &groups, action)?;
+    }
+
+    let action_label = match (action, dry_run) {
+        (DupesAction::List, _) => "duplicate",
+        (DupesAction::Hardlink, true) => "would be hard linked",
+        (DupesAction::Hardlink, false) => "hard linked",
+        (DupesAction::Trash, true) => "would be trashed",
+        (DupesAction::Trash, false) => "trashed",
+    };
+
+    let mut duplicates_size = 0;
+    for group in groups.iter() {
+        for duplicate in group.duplicates.iter() {
+            duplicates_size += fs::metadata(duplicate).map_or(0, |metadata| metadata.len());
+        }
+    }
+
+    match use_json_format {
+        true => {
+            let summary = json!({
+                "groups": groups,
+                "duplicates_size": duplicates_size,
+                "failed": failed,
+            });
+            let summary_json = serde_json::to_string(&summary)?;
+            println!("{summary_json}");
+        }
+        false => {
+            // Similar images differ in resolution, which is worth showing to pick the right one
+            let describe = |path: &Path| match perceptual_threshold {
+                Some(_) => match utils::common::get_image_dims(path) {
+                    Ok((width, height)) => format!("{} {width}x{height}", path.display()),
+                    Err(_) => path.display().to_string(),
+                },
+                None => path.display().to_string(),
+            };
+
+            for group in groups.iter() {
+                println!("{}:", group.id);
+                println!("- {} (kept)", describe(&group.keep));
+                for duplicate in group.duplicates.iter() {
+                    match failed.contains(duplicate) {
+                        true => println!("- {} (failed)", describe(duplicate)),
+                        false => println!("- {} ({})", describe(duplicate), action_label),
+                    }
+                }
+                println!();
+            }
+
+            let duplicates_count: usize = groups.iter().map(|group| group.duplicates.len()).sum();
+            println!(
+                "{} groups, {} duplicates ({} bytes)",
+                groups.len(),
+                duplicates_count,
+                duplicates_size
+            );
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("failed to deduplicate {} images", failed.len());
+    }
+
+    Ok(())
+}
+
+/// Group images whose perceptual hashes are within the threshold of each other, transitively
+fn group_similar_images(images: &[PerceptualImage], threshold: u32) -> Vec<(String, Vec<PathBuf>)> {
+    let mut parents: Vec<usize> = (0..images.len()).collect();
+    for i in 0..images.len() {
+        for j in (i + 1)..images.len() {
+            if utils::common::get_hash_distance(images[i].hash, images[j].hash) <= threshold {
+                let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..images.len() {
+        members
+            .entry(find_root(&mut parents, i))
+            .or_default()
+            .push(i);
+    }
+
+    let mut groups: Vec<_> = members
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .map(|indices| {
+            let id = utils::common::format_perceptual_hash(images[indices[0]].hash);
+            let mut paths: Vec<_> = indices.iter().map(|i| images[*i].path.clone()).collect();
+            paths.sort();
+            (id, paths)
+        })
+        .collect();
+    groups.sort_by(|(_, a), (_, b)| a[0].cmp(&b[0]));
+
+    groups
+}
+
+/// Find the representative of a group in a union-find forest, compressing the path on the way
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+
+    index
+}
+
+fn split_group(id: String, mut paths: Vec<PathBuf>, keep: KeepStrategy) -> Result<DuplicateGroup> {
+    let kept_index = match keep {
+        KeepStrategy::Oldest => {
+            let mut modified_dates = vec![];
+            for path in paths.iter() {
+                modified_dates.push(fs::metadata(path)?.modified()?);
+            }
+
+            (0..paths.len()).min_by_key(|i| modified_dates[*i])
+        }
+        KeepStrategy::Shortest => (0..paths.len()).min_by_key(|i| paths[*i].as_os_str().len()),
+        KeepStrategy::Resolution => {
+            let mut resolutions = vec![];
+            for path in paths.iter() {
+                let (width, height) = utils::common::get_image_dims(path)?;
+                resolutions.push(width as u64 * height as u64);
+            }
+
+            (0..paths.len()).min_by_key(|i| Reverse(resolutions[*i]))
+        }
+    }
+    .context("group of duplicates should not be empty")?;
+
+    let kept = paths.remove(kept_index);
+    Ok(DuplicateGroup {
+        id,
+        keep: kept,
+        duplicates: paths,
+    })
+}
+
+/// Apply the action to the duplicates of each group, returning the ones that failed
+fn apply_action(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    groups: &[DuplicateGroup],
+    action: DupesAction,
+) -> Result<Vec<PathBuf>> {
+    let mut failed = vec![];
+    let mut trashed = vec![];
+
+    for group in groups.iter() {
+        for duplicate in group.duplicates.iter() {
+            let result = match action {
+                DupesAction::List => Ok(()),
+                DupesAction::Hardlink => replace_with_hard_link(&group.keep, duplicate),
+                DupesAction::Trash => trash::delete(duplicate)
+                    .context(format!("failed to trash {}", duplicate.display())),
+            };
+
+            match result {
+                Ok(_) if action == DupesAction::Trash => trashed.push(duplicate.as_path()),
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("{e}");
+                    failed.push(duplicate.clone());
+                }
+            }
+        }
+    }
+
+    // Hard links keep every path in place, only trashed copies need their metadata moved
+    if !trashed.is_empty() {
+        merge_trashed_metas(configuration, roots, groups, &trashed)?;
+    }
+
+    Ok(failed)
+}
+
+fn replace_with_hard_link(kept: &Path, duplicate: &Path) -> Result<()> {
+    let file_name = duplicate
+        .file_name()
+        .context("image file should have a filename")?
+        .to_string_lossy();
+    let link_path = duplicate.with_file_name(format!(".{file_name}.kanumi-link"));
+
+    fs::hard_link(kept, &link_path).context(format!(
+        "failed to link {} to {}, both files must be on the same filesystem",
+        duplicate.display(),
+        kept.display()
+    ))?;
+
+    if let Err(e) = fs::rename(&link_path, duplicate) {
+        fs::remove_file(&link_path)?;
+        return Err(e.into());
+    }
+
+    debug!("hard linked {} to {}", duplicate.display(), kept.display());
+    Ok(())
+}
+
+/// Drop the metadatas of trashed copies, merging them into the metadata of the kept copy
+fn merge_trashed_metas(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    groups: &[DuplicateGroup],
+    trashed: &[&Path],
+) -> Result<()> {
+    let all_metas = utils::common::load_all_image_metas(roots)?;
+
+    // Merged metadata of each group, along with the file to add it to when the kept copy had none
+    let mut merged_metas: Vec<(ImageMeta, Option<&Path>)> = vec![];
+    for group in groups.iter() {
+        let trashed_metas: Vec<&ImageMeta> = all_metas
+            .iter()
+            .filter(|meta| {
+                group.duplicates.contains(&meta.path) && trashed.contains(&meta.path.as_path())
+            })
+            .collect();
+        if trashed_metas.is_empty() {
+            continue;
+        }
+
+        let (mut merged, target) = match all_metas.iter().find(|meta| meta.path == group.keep) {
+            Some(kept_meta) => (kept_meta.clone(), None),
+            None => {
+                let target = roots
+                    .iter()
+                    .find(|root| root.contains(&group.keep))
+                    .map(|root| root.metadata_path.as_path());
+                (
+                    ImageMeta::create_from_image(&group.keep, &configuration.theme)?,
+                    target,
+                )
+            }
+        };
+
+        for trashed_meta in trashed_metas.iter() {
+            merged.merge(trashed_meta);
+        }
+
+        merged_metas.push((merged, target));
+    }
+
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            metas.retain(|meta| !trashed.contains(&meta.path.as_path()));
+
+            for (merged, target) in merged_metas.iter() {
+                match metas.iter_mut().find(|meta| meta.path == merged.path) {
+                    Some(existing) => *existing = merged.clone(),
+                    None if *target == Some(metadata_path) => metas.push(merged.clone()),
+                    None => {}
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,288 @@
+use anyhow::{Context, Result};
+use image::{imageops::FilterType, ImageReader};
+use log::{debug, info, warn};
+use serde_json::json;
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::Path,
+};
+
+use super::{args::FilterArgs, list};
+use crate::{
+    models::{ImageMeta, ImageRoot},
+    utils,
+};
+
+const THUMBNAILS_DIR: &str = "thumbnails";
+const IMAGES_DIR: &str = "images";
+const PAGES_DIR: &str = "pages";
+
+const STYLE: &str = r#"
+body { margin: 0; padding: 1rem; background: #1e1e2e; color: #cdd6f4; font-family: sans-serif; }
+a { color: #89b4fa; }
+header { display: flex; flex-wrap: wrap; gap: 1rem; align-items: center; margin-bottom: 1rem; }
+.grid { display: grid; grid-template-columns: repeat(auto-fill, minmax(220px, 1fr)); gap: 1rem; }
+.card { background: #313244; border-radius: 6px; overflow: hidden; text-decoration: none; color: inherit; }
+.card img { width: 100%; aspect-ratio: 16 / 10; object-fit: cover; display: block; }
+.card span { display: block; padding: 0.5rem; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+.image { max-width: 100%; max-height: 80vh; display: block; margin-bottom: 1rem; }
+.tag { display: inline-block; background: #45475a; border-radius: 4px; padding: 0.1rem 0.4rem; margin: 0.1rem; }
+.swatch { display: inline-block; width: 1.5rem; height: 1.5rem; border-radius: 4px; vertical-align: middle; margin-right: 0.2rem; }
+th { text-align: left; padding-right: 1rem; vertical-align: top; }
+"#;
+
+const TAG_FILTER_SCRIPT: &str = r#"
+document.getElementById("tag").addEventListener("change", (event) => {
+  const tag = event.target.value;
+  for (const card of document.querySelectorAll(".card")) {
+    const tags = JSON.parse(card.dataset.tags);
+    card.hidden = tag !== "" && !tags.includes(tag);
+  }
+});
+"#;
+
+/// Write a static HTML gallery of the images matching the filters to the output directory
+pub fn export_html(
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
+    out_dir: &Path,
+    thumbnail_size: u32,
+) -> Result<()> {
+    let metas = utils::common::load_root_metas(roots)?;
+    let metas = list::filter_metas(roots, metas, filters)?;
+
+    for dir in [THUMBNAILS_DIR, IMAGES_DIR, PAGES_DIR] {
+        fs::create_dir_all(out_dir.join(dir))
+            .with_context(|| format!("failed to create directory: {}", out_dir.display()))?;
+    }
+
+    // Copies of the same image are only exported once
+    let mut exported_ids = HashSet::new();
+    let mut exported_metas = vec![];
+    for meta in metas.iter() {
+        // The id names the exported files, anything but a hash could write outside of them
+        if !is_valid_id(&meta.id) {
+            warn!("skipping image with invalid id: {}", meta.path.display());
+            continue;
+        }
+
+        if !exported_ids.insert(meta.id.as_str()) {
+            debug!("skipping copy: {}", meta.path.display());
+            continue;
+        }
+
+        match export_image(meta, out_dir, thumbnail_size) {
+            Ok(image_file) => exported_metas.push((meta, image_file)),
+            Err(e) => warn!("failed to export {}: {}", meta.path.display(), e),
+        }
+    }
+
+    let mut index = vec![];
+    for (meta, image_file) in exported_metas.iter() {
+        fs::write(
+            out_dir.join(PAGES_DIR).join(format!("{}.html", meta.id)),
+            render_image_page(meta, image_file),
+        )?;
+
+        index.push(json!({
+            "id": meta.id,
+            "title": meta.title,
+            "description": meta.description,
+            "width": meta.width,
+            "height": meta.height,
+            "scores": meta.scores,
+            "tags": meta.tags,
+            "theme": meta.theme,
+            "colors": meta.colors,
+            "palette": meta.palette,
+            "image": format!("{IMAGES_DIR}/{image_file}"),
+            "thumbnail": format!("{THUMBNAILS_DIR}/{}", get_thumbnail_file(meta, thumbnail_size)),
+            "page": format!("{PAGES_DIR}/{}.html", meta.id),
+        }));
+    }
+
+    let metas: Vec<&ImageMeta> = exported_metas.iter().map(|(meta, _)| *meta).collect();
+    fs::write(
+        out_dir.join("index.html"),
+        render_index_page(&metas, thumbnail_size),
+    )?;
+    fs::write(
+        out_dir.join("index.json"),
+        serde_json::to_string_pretty(&index)?,
+    )?;
+
+    info!("exported {} images to: {}", index.len(), out_dir.display());
+    println!("{}", out_dir.join("index.html").display());
+    Ok(())
+}
+
+/// Copy the image and write its thumbnail, returning the file name of the copy
+fn export_image(meta: &ImageMeta, out_dir: &Path, thumbnail_size: u32) -> Result<String> {
+    let image_file = match meta.path.extension() {
+        Some(extension) => format!("{}.{}", meta.id, extension.to_string_lossy()),
+        None => meta.id.clone(),
+    };
+
+    let image_path = out_dir.join(IMAGES_DIR).join(&image_file);
+    fs::copy(&meta.path, &image_path)?;
+
+    // The size is part of the name, so that exporting again with another size does not reuse
+    // thumbnails of the previous one
+    let thumbnail_path = out_dir
+        .join(THUMBNAILS_DIR)
+        .join(get_thumbnail_file(meta, thumbnail_size));
+    if !thumbnail_path.exists() {
+        let image = ImageReader::open(&image_path)?
+            .with_guessed_format()?
+            .decode()?;
+
+        image
+            .resize(thumbnail_size, thumbnail_size, FilterType::Triangle)
+            .to_rgb8()
+            .save(&thumbnail_path)?;
+    }
+
+    Ok(image_file)
+}
+
+fn get_thumbnail_file(meta: &ImageMeta, thumbnail_size: u32) -> String {
+    format!("{}-{thumbnail_size}.jpg", meta.id)
+}
+
+/// Whether an id is a blake3 hash, as written by `compute_blake3_hash`
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 64 && id.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+fn render_index_page(metas: &[&ImageMeta], thumbnail_size: u32) -> String {
+    let tags: BTreeSet<&str> = metas
+        .iter()
+        .flat_map(|meta| meta.tags.iter().map(String::as_str))
+        .collect();
+
+    let tag_options: String = tags
+        .iter()
+        .map(|tag| format!(r#"<option value="{0}">{0}</option>"#, escape_html(tag)))
+        .collect();
+
+    let cards: String = metas
+        .iter()
+        .map(|meta| {
+            format!(
+                r#"<a class="card" href="{PAGES_DIR}/{id}.html" data-tags="{tags}"><img src="{THUMBNAILS_DIR}/{thumbnail}" alt="" loading="lazy"><span>{title}</span></a>"#,
+                id = meta.id,
+                thumbnail = get_thumbnail_file(meta, thumbnail_size),
+                tags = escape_html(&serde_json::to_string(&meta.tags).unwrap_or_default()),
+                title = escape_html(&meta.title),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>kanumi gallery</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<header>
+<strong>{count} images</strong>
+<label>Tag <select id="tag"><option value="">all</option>{tag_options}</select></label>
+<a href="index.json">index.json</a>
+</header>
+<main class="grid">
+{cards}
+</main>
+<script>{TAG_FILTER_SCRIPT}</script>
+</body>
+</html>
+"#,
+        count = metas.len(),
+    )
+}
+
+fn render_image_page(meta: &ImageMeta, image_file: &str) -> String {
+    let scores: String = meta
+        .scores
+        .iter()
+        .map(|score| {
+            format!(
+                r#"<span class="tag">{}: {}</span>"#,
+                escape_html(&score.name),
+                score.value
+            )
+        })
+        .collect();
+
+    let tags: String = meta
+        .tags
+        .iter()
+        .map(|tag| format!(r#"<span class="tag">{}</span>"#, escape_html(tag)))
+        .collect();
+
+    let colors: Vec<_> = meta.colors.iter().map(|color| color.name()).collect();
+    let palette: String = meta
+        .palette
+        .iter()
+        .map(|hex| {
+            format!(
+                r#"<span class="swatch" title="{0}" style="background: {0}"></span>"#,
+                escape_html(hex)
+            )
+        })
+        .collect();
+
+    let theme = meta.theme.map(|theme| theme.name()).unwrap_or("unknown");
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<header><a href="../index.html">&larr; Gallery</a><strong>{title}</strong></header>
+<a href="../{IMAGES_DIR}/{image_file}"><img class="image" src="../{IMAGES_DIR}/{image_file}" alt="{title}"></a>
+<p>{description}</p>
+<table>
+<tr><th>Resolution</th><td>{width}x{height}</td></tr>
+<tr><th>Scores</th><td>{scores}</td></tr>
+<tr><th>Tags</th><td>{tags}</td></tr>
+<tr><th>Colors</th><td>{palette} {colors}</td></tr>
+<tr><th>Theme</th><td>{theme}</td></tr>
+</table>
+</body>
+</html>
+"#,
+        title = escape_html(&meta.title),
+        image_file = escape_html(image_file),
+        description = escape_html(&meta.description),
+        width = meta.width,
+        height = meta.height,
+        colors = colors.join(", "),
+    )
+}
+
+fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
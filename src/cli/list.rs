@@ -1,33 +1,117 @@
 use anyhow::Result;
+use chrono::Local;
 use log::{debug, info};
-use std::{
-    ops::RangeInclusive,
-    path::{Path, PathBuf},
+use std::ops::RangeInclusive;
+
+use super::args::FilterArgs;
+use crate::{
+    models::{Configuration, ConfigurationWalker, ImageMeta, ImageRoot, ScoreFilter},
+    utils,
 };
 
-use crate::{models::ScoreFilter, utils};
+/// Maximum distance between close colors when none is given
+const DEFAULT_COLOR_DISTANCE: f32 = 10.0;
 
 pub fn list_images_using_metadata(
-    root_images_dir: &Path,
-    metadata_path: &Path,
-    active_directories: Option<Vec<PathBuf>>,
-    score_filters: Option<Vec<ScoreFilter>>,
-    width_range: Option<RangeInclusive<usize>>,
-    height_range: Option<RangeInclusive<usize>>,
-    tags: Option<Vec<String>>,
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
     use_json_format: bool,
 ) -> Result<()> {
     debug!("loading image metadatas");
-    let metas = utils::common::load_image_metas(metadata_path)?;
+    let metas = utils::common::load_root_metas(roots)?;
+    let filtered_metas = filter_metas(roots, metas, filters)?;
 
-    info!("active_directories: {:?}", active_directories);
-    info!("score_filters: {:?}", score_filters);
-    info!("width_range: {:?}", width_range);
-    info!("height_range: {:?}", height_range);
+    debug!("about to render output");
+    match use_json_format {
+        true => {
+            info!("outputting as json");
+            let metas_json = serde_json::to_string(&filtered_metas)?;
+            println!("{}", metas_json);
+        }
+        false => {
+            info!("outputting image paths only");
+            for meta in filtered_metas.iter() {
+                println!("{}", meta.path.display());
+            }
+        }
+    };
+
+    Ok(())
+}
+
+/// Merge the filters given on the command line with the ones preset in configuration
+pub fn resolve_filters(filters: FilterArgs, configuration: &Configuration) -> Result<FilterArgs> {
+    if filters.ignore_config {
+        info!("ignore_config flag has been added");
+        return Ok(FilterArgs {
+            scores: resolve_score_filters(filters.scores, configuration)?,
+            ..filters
+        });
+    }
+
+    let at = filters.at.unwrap_or_else(|| Local::now().naive_local());
+    let profile = configuration.resolve_profile_name(filters.profile.as_deref(), at)?;
+    let preset = configuration.get_profile(profile)?;
+    debug!("using preset filters: {:?}", preset);
+
+    Ok(FilterArgs {
+        active_directories: filters
+            .active_directories
+            .or(preset.active_directories.clone()),
+        scores: resolve_score_filters(filters.scores.or(preset.scores.clone()), configuration)?,
+        width_range: filters.width_range.or(preset.width_range.clone()),
+        height_range: filters.height_range.or(preset.height_range.clone()),
+        tags: filters.tags.or(preset.tags.clone()),
+        colors: filters.colors.or(preset.colors.clone()),
+        theme: filters.theme.or(preset.theme),
+        near_colors: match filters.near_colors {
+            Some(near_colors) => Some(near_colors),
+            None => preset.get_near_colors()?,
+        },
+        distance: filters.distance.or(preset.distance),
+        palette_file: filters.palette_file.or(preset.palette_file.clone()),
+        palette_distance: filters.palette_distance.or(preset.palette_distance),
+        at: filters.at,
+        root_name: filters.root_name,
+        profile: filters.profile,
+        ignore_config: filters.ignore_config,
+    })
+}
+
+/// Check that the filtered scores are defined, and complete them with the defaults of their definition
+fn resolve_score_filters(
+    score_filters: Option<Vec<ScoreFilter>>,
+    configuration: &Configuration,
+) -> Result<Option<Vec<ScoreFilter>>> {
+    let Some(mut score_filters) = score_filters else {
+        return Ok(None);
+    };
+
+    for score_filter in score_filters.iter_mut() {
+        configuration.ensure_score_is_defined(&score_filter.name)?;
+        score_filter.default = configuration
+            .scores
+            .get(&score_filter.name)
+            .and_then(|score| score.default);
+    }
+
+    Ok(Some(score_filters))
+}
+
+/// Keep only the metadatas that match the given filters
+pub fn filter_metas(
+    roots: &[ImageRoot],
+    metas: Vec<ImageMeta>,
+    filters: &FilterArgs,
+) -> Result<Vec<ImageMeta>> {
+    info!("active_directories: {:?}", filters.active_directories);
+    info!("score_filters: {:?}", filters.scores);
+    info!("width_range: {:?}", filters.width_range);
+    info!("height_range: {:?}", filters.height_range);
 
     let mut filtered_metas = vec![];
 
-    if let Some(active_dirs) = active_directories {
+    if let Some(active_dirs) = &filters.active_directories {
         for active_dir in active_dirs.iter() {
             info!("filter using active directory: {:?}", active_dir);
             let matching_metas: Vec<_> = metas
@@ -36,7 +120,14 @@ pub fn list_images_using_metadata(
                     let base_directory = if active_dir.is_absolute() {
                         active_dir.clone()
                     } else {
-                        root_images_dir.join(active_dir)
+                        let root = roots
+                            .iter()
+                            .find(|root| meta.root.as_ref() == Some(&root.name));
+
+                        match root {
+                            Some(root) => root.path.join(active_dir),
+                            None => return false,
+                        }
                     };
 
                     meta.path
@@ -53,14 +144,18 @@ pub fn list_images_using_metadata(
         filtered_metas.extend(metas);
     }
 
-    if width_range.is_some() || height_range.is_some() {
+    if filters.width_range.is_some() || filters.height_range.is_some() {
         info!("applying dimensions filter...");
         filtered_metas.retain(|meta| {
-            utils::common::image_matches_dims(&meta.path, &width_range, &height_range)
+            utils::common::image_matches_dims(
+                &meta.path,
+                &filters.width_range,
+                &filters.height_range,
+            )
         });
     }
 
-    if let Some(score_filters) = score_filters {
+    if let Some(score_filters) = &filters.scores {
         info!("applying image meta score filters...");
 
         for score_filter in score_filters.iter() {
@@ -68,7 +163,7 @@ pub fn list_images_using_metadata(
         }
     }
 
-    if let Some(tags) = tags {
+    if let Some(tags) = &filters.tags {
         info!("applying tags filters...");
 
         for tag in tags.iter() {
@@ -76,34 +171,95 @@ pub fn list_images_using_metadata(
         }
     }
 
-    debug!("about to render output");
-    match use_json_format {
-        true => {
-            info!("outputting as json");
-            let metas_json = serde_json::to_string(&filtered_metas)?;
-            println!("{}", metas_json);
+    if let Some(colors) = &filters.colors {
+        info!("applying colors filters...");
+
+        for color in colors.iter() {
+            filtered_metas.retain(|meta| meta.colors.contains(color));
         }
-        false => {
-            info!("outputting image paths only");
-            for meta in filtered_metas.iter() {
-                println!("{}", meta.path.display());
-            }
+    }
+
+    if let Some(theme) = filters.theme {
+        info!("applying theme filter...");
+        filtered_metas.retain(|meta| meta.theme == Some(theme));
+    }
+
+    if let Some(near_colors) = &filters.near_colors {
+        info!("applying near colors filters...");
+        let distance = filters.distance.unwrap_or(DEFAULT_COLOR_DISTANCE);
+
+        for near_color in near_colors.iter() {
+            filtered_metas.retain(|meta| {
+                get_palette(meta)
+                    .into_iter()
+                    .any(|color| utils::palette::get_color_distance(color, *near_color) <= distance)
+            });
         }
-    };
+    }
 
-    Ok(())
+    if let Some(palette_file) = &filters.palette_file {
+        info!("ranking images by palette: {}", palette_file.display());
+        let scheme = utils::palette::load_palette_file(palette_file)?;
+
+        let mut ranked_metas: Vec<(ImageMeta, f32)> = filtered_metas
+            .into_iter()
+            .filter_map(|meta| {
+                let score = get_palette_match_score(&get_palette(&meta), &scheme)?;
+                debug!("palette score of {}: {}", meta.path.display(), score);
+                Some((meta, score))
+            })
+            .collect();
+
+        if let Some(palette_distance) = filters.palette_distance {
+            ranked_metas.retain(|(_, score)| *score <= palette_distance);
+        }
+
+        ranked_metas.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        filtered_metas = ranked_metas.into_iter().map(|(meta, _)| meta).collect();
+    }
+
+    Ok(filtered_metas)
+}
+
+fn get_palette(meta: &ImageMeta) -> Vec<[u8; 3]> {
+    meta.palette
+        .iter()
+        .filter_map(|hex| utils::palette::parse_hex(hex).ok())
+        .collect()
+}
+
+/// Mean distance between the colors of an image palette and their closest scheme color.
+/// Most dominant colors weigh more. Lower is better, `None` when the image was not analyzed
+fn get_palette_match_score(palette: &[[u8; 3]], scheme: &[[u8; 3]]) -> Option<f32> {
+    let mut total_distance = 0.0;
+    let mut total_weight = 0.0;
+
+    for (rank, color) in palette.iter().enumerate() {
+        let closest_distance = scheme
+            .iter()
+            .map(|scheme_color| utils::palette::get_color_distance(*color, *scheme_color))
+            .min_by(f32::total_cmp)?;
+
+        let weight = 1.0 / (rank + 1) as f32;
+        total_distance += weight * closest_distance;
+        total_weight += weight;
+    }
+
+    (total_weight > 0.0).then(|| total_distance / total_weight)
 }
 
+#[allow(dead_code)]
 fn filter_images_without_using_metadata(
-    base_directory: PathBuf,
+    root: &ImageRoot,
+    walker: &ConfigurationWalker,
     width_range: Option<RangeInclusive<usize>>,
     height_range: Option<RangeInclusive<usize>>,
 ) -> Result<()> {
     info!("width_range: {:?}", width_range);
     info!("height_range: {:?}", height_range);
 
-    info!("about to run WalkDir on {}", base_directory.display());
-    let mut images = utils::common::get_all_images(&base_directory)?;
+    info!("about to walk through {}", root.path.display());
+    let mut images = utils::common::get_root_images(root, walker)?;
 
     if width_range.is_some() || height_range.is_some() {
         info!("applying dimensions filter...");
@@ -116,3 +272,38 @@ fn filter_images_without_using_metadata(
 
     todo!("not fully supported yet!")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [215, 40, 40];
+    const BLUE: [u8; 3] = [40, 90, 210];
+    const NAVY: [u8; 3] = [20, 30, 90];
+
+    #[test]
+    fn ranks_palettes_closest_to_scheme_first() {
+        let scheme = [BLUE, NAVY];
+        let palettes = [
+            vec![RED, BLUE],
+            vec![BLUE, RED],
+            vec![NAVY, BLUE],
+            vec![RED],
+        ];
+
+        let scores: Vec<f32> = palettes
+            .iter()
+            .map(|palette| get_palette_match_score(palette, &scheme).unwrap())
+            .collect();
+        assert_eq!(scores[2], 0.0);
+        assert!(scores[2] < scores[1]);
+        assert!(scores[1] < scores[0]);
+        assert!(scores[0] < scores[3]);
+    }
+
+    #[test]
+    fn does_not_score_without_palette_or_scheme() {
+        assert_eq!(get_palette_match_score(&[], &[BLUE]), None);
+        assert_eq!(get_palette_match_score(&[BLUE], &[]), None);
+    }
+}
@@ -1,10 +1,10 @@
 use anyhow::{bail, Context, Result};
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use log::{debug, info};
-use std::ffi::OsString;
+use log::{debug, info, warn};
+use std::{collections::BTreeMap, ffi::OsString, fs, path::Path};
 
 use crate::{
-    models::{Configuration, ImageMeta},
+    models::{Configuration, ImageMeta, ImageRoot, MetadataPatch},
     utils,
 };
 
@@ -14,7 +14,17 @@ pub fn handle_metadata_command(
     command: MetadataCommands,
     configuration: &Configuration,
 ) -> Result<()> {
-    let metadatas = utils::common::load_image_metas(&configuration.metadata_path)?;
+    let root_name = match &command {
+        MetadataCommands::Search { root_name, .. } => root_name.as_deref(),
+        _ => None,
+    };
+
+    // Metadatas outside of every root are kept, so that they can still be seen and fixed
+    let roots = utils::common::get_image_roots(configuration, root_name)?;
+    let metadatas = match root_name {
+        Some(_) => utils::common::load_root_metas(&roots)?,
+        None => utils::common::load_tagged_metas(&roots)?,
+    };
 
     match command {
         MetadataCommands::Show => {
@@ -23,18 +33,32 @@ pub fn handle_metadata_command(
             Ok(())
         }
         MetadataCommands::Get { identifier } => get_metadata(&identifier, &metadatas),
+        MetadataCommands::Check { use_json_format } => {
+            let all_metadatas = utils::common::load_all_image_metas(&roots)?;
+            super::check_metadata(configuration, &roots, &all_metadatas, use_json_format)
+        }
         MetadataCommands::Edit {
             identifier,
             payload,
-        } => update_metadata(&identifier, &payload, &metadatas),
+        } => update_metadata(configuration, &roots, &identifier, &payload, &metadatas),
+        MetadataCommands::Import { file } => {
+            import_metadatas(configuration, &roots, &file, &metadatas)
+        }
+        MetadataCommands::Analyze { identifier, force } => analyze_metadatas(
+            configuration,
+            &roots,
+            identifier.as_ref(),
+            force,
+            &metadatas,
+        ),
         MetadataCommands::Generate { image, dry_run: _ } => {
             info!("generating default metadata...");
-            let meta = ImageMeta::create_from_image(&image)?;
+            let meta = ImageMeta::create_from_image(&image, &configuration.theme)?;
             let json = serde_json::to_string(&meta)?;
             println!("{}", json);
             Ok(())
 
-            // let images = get_all_images(&base_dir)?;
+            // let images = get_all_images(&base_dir, &configuration.walker)?;
             // let mut metadatas = vec![];
             // for image in images.iter() {
             //     let meta = ImageMeta::create_from_image(image)?;
@@ -47,9 +71,10 @@ pub fn handle_metadata_command(
         }
         MetadataCommands::Search {
             query,
+            root_name: _,
             use_json_format,
         } => {
-            let result = search_metadata(configuration, query, &metadatas)?;
+            let result = search_metadata(&roots, query, &metadatas)?;
             if let Some(metadata) = result {
                 match use_json_format {
                     true => {
@@ -68,8 +93,8 @@ pub fn handle_metadata_command(
     }
 }
 
-fn search_metadata(
-    configuration: &Configuration,
+pub fn search_metadata(
+    roots: &[ImageRoot],
     query: OsString,
     metadatas: &[ImageMeta],
 ) -> Result<Option<ImageMeta>> {
@@ -84,8 +109,11 @@ fn search_metadata(
             return Ok(Some(meta.clone()));
         }
 
-        let root_images_dir = configuration
-            .root_images_dir
+        let root_images_dir = roots
+            .iter()
+            .find(|root| meta.root.as_ref() == Some(&root.name))
+            .context("meta should belong to a root")?
+            .path
             .to_str()
             .context("root images dir should be a valid string")?;
 
@@ -135,18 +163,166 @@ fn search_metadata(
 }
 
 fn update_metadata(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
     identifier: &OsString,
-    _payload: &OsString,
+    payload: &OsString,
     metadatas: &[ImageMeta],
 ) -> Result<()> {
     let identifier = identifier.to_string_lossy();
-    let meta = utils::common::get_image_by_path_or_id(&identifier, metadatas)?;
-    if meta.is_none() {
+    let Some(meta) = utils::common::get_image_by_path_or_id(&identifier, metadatas)? else {
         bail!("no matching metadata for: {identifier}");
+    };
+
+    let patch: MetadataPatch = match serde_json::from_str(&payload.to_string_lossy()) {
+        Ok(patch) => patch,
+        Err(e) => bail!("invalid payload: {e}"),
+    };
+    patch.validate(configuration)?;
+
+    let mut meta = meta.clone();
+    patch.apply(&mut meta);
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            let stored = metas
+                .iter_mut()
+                .find(|stored| stored.id == meta.id && stored.path == meta.path);
+            if let Some(stored) = stored {
+                patch.apply(stored);
+            }
+
+            Ok(())
+        })?;
+    }
+
+    info!("updated metadata: {}", meta.path.display());
+    println!("{}", serde_json::to_string(&meta)?);
+    Ok(())
+}
+
+fn import_metadatas(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    file: &Path,
+    metadatas: &[ImageMeta],
+) -> Result<()> {
+    let content = fs::read_to_string(file)
+        .context(format!("failed to read import file: {}", file.display()))?;
+    let patches: BTreeMap<String, MetadataPatch> = match serde_json::from_str(&content) {
+        Ok(patches) => patches,
+        Err(e) => bail!("invalid import file: {e}"),
+    };
+
+    // Check every entry before writing, so that a bad one does not leave a partial import
+    let mut updates = vec![];
+    for (identifier, patch) in patches.iter() {
+        let Some(meta) = utils::common::get_image_by_path_or_id(identifier, metadatas)? else {
+            bail!("no matching metadata for: {identifier}");
+        };
+
+        if let Err(e) = patch.validate(configuration) {
+            bail!("invalid fields for {identifier}: {e}");
+        }
+
+        updates.push((meta, patch));
+    }
+
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            for stored in metas.iter_mut() {
+                let update = updates
+                    .iter()
+                    .find(|(meta, _)| meta.id == stored.id && meta.path == stored.path);
+                if let Some((_, patch)) = update {
+                    patch.apply(stored);
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    info!(
+        "imported {} metadatas from: {}",
+        updates.len(),
+        file.display()
+    );
+    println!("updated {} images", updates.len());
+    Ok(())
+}
+
+fn analyze_metadatas(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    identifier: Option<&OsString>,
+    force: bool,
+    metadatas: &[ImageMeta],
+) -> Result<()> {
+    let targets: Vec<&ImageMeta> = match identifier {
+        Some(identifier) => {
+            let identifier = identifier.to_string_lossy();
+            match utils::common::get_image_by_path_or_id(&identifier, metadatas)? {
+                Some(meta) => vec![meta],
+                None => bail!("no matching metadata for: {identifier}"),
+            }
+        }
+        None => metadatas
+            .iter()
+            .filter(|meta| force || !meta.is_analyzed())
+            .collect(),
+    };
+
+    info!("analyzing {} images...", targets.len());
+    let mut analyzed_metas = vec![];
+    for meta in targets.into_iter() {
+        let mut meta = meta.clone();
+        if let Err(e) = meta.analyze(&configuration.theme) {
+            warn!("failed to analyze {}: {}", meta.path.display(), e);
+            continue;
+        }
+
+        let colors: Vec<_> = meta.colors.iter().map(|color| color.name()).collect();
+        let theme = match (meta.theme, meta.theme_confidence) {
+            (Some(theme), _) if meta.has_manual_theme() => format!("{} (manual)", theme.name()),
+            (Some(theme), Some(confidence)) => format!("{} ({confidence})", theme.name()),
+            (Some(theme), None) => theme.name().to_owned(),
+            (None, _) => String::from("unknown"),
+        };
+        println!(
+            "{}: {} ({}), theme: {}",
+            meta.path.display(),
+            colors.join(", "),
+            meta.palette.join(", "),
+            theme
+        );
+        analyzed_metas.push(meta);
+    }
+
+    if analyzed_metas.is_empty() {
+        return Ok(());
+    }
+
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            for meta in metas.iter_mut() {
+                let analyzed = analyzed_metas
+                    .iter()
+                    .find(|analyzed| analyzed.id == meta.id && analyzed.path == meta.path);
+
+                if let Some(analyzed) = analyzed {
+                    meta.colors = analyzed.colors.clone();
+                    meta.palette = analyzed.palette.clone();
+                    meta.theme = analyzed.theme;
+                    meta.theme_confidence = analyzed.theme_confidence;
+                    meta.theme_source = analyzed.theme_source;
+                }
+            }
+
+            Ok(())
+        })?;
     }
 
-    // let meta = meta.unwrap();
-    todo!()
+    Ok(())
 }
 
 fn get_metadata(identifier: &OsString, metadatas: &[ImageMeta]) -> Result<()> {
@@ -1,14 +1,56 @@
 pub mod args;
+pub mod check;
 pub mod config;
+#[cfg(unix)]
+pub mod ctl;
+#[cfg(unix)]
+pub mod daemon;
+pub mod duel;
+pub mod dupes;
+pub mod export;
 pub mod list;
 pub mod metadata;
+pub mod preview;
+pub mod rate;
 pub mod scan;
+pub mod serve;
+pub mod similar;
+pub mod thumb;
+pub mod tui;
+pub mod verify;
+pub mod watch;
 
 pub use self::args::Cli;
 pub use self::args::Commands;
 pub use self::args::ConfigurationCommands;
+#[cfg(unix)]
+pub use self::args::CtlCommands;
+pub use self::args::DupesAction;
+pub use self::args::ExportCommands;
+pub use self::args::FilterArgs;
+pub use self::args::KeepStrategy;
 pub use self::args::MetadataCommands;
+pub use self::args::PreviewProtocol;
+pub use self::args::RateOrder;
+pub use self::check::check_metadata;
 pub use self::config::handle_config_command;
+#[cfg(unix)]
+pub use self::ctl::handle_ctl_command;
+#[cfg(unix)]
+pub use self::daemon::run_daemon;
+pub use self::duel::run_duels;
+pub use self::dupes::handle_dupes;
+pub use self::export::export_html;
 pub use self::list::list_images_using_metadata;
+pub use self::list::resolve_filters;
 pub use self::metadata::handle_metadata_command;
+pub use self::preview::preview_image;
+pub use self::rate::rate_images;
 pub use self::scan::scan_images;
+pub use self::serve::serve_api;
+pub use self::similar::find_similar_images;
+pub use self::thumb::generate_thumbnails;
+pub use self::thumb::print_thumbnail;
+pub use self::tui::run_tui;
+pub use self::verify::verify_images;
+pub use self::watch::watch_images;
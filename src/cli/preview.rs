@@ -0,0 +1,212 @@
+use anyhow::Result;
+use image::{imageops::FilterType, DynamicImage, ImageReader};
+use log::{debug, info, warn};
+use ratatui::crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, terminal,
+};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Write},
+    path::Path,
+};
+
+use super::PreviewProtocol;
+use crate::{
+    models::{ImageMeta, ImageRoot, ThumbnailSize},
+    utils,
+};
+
+/// Approximate size of a terminal cell in pixels, as terminals do not always report it
+const CELL_WIDTH: u32 = 10;
+const CELL_HEIGHT: u32 = 20;
+
+const DEFAULT_COLUMNS: u32 = 80;
+const DEFAULT_ROWS: u32 = 24;
+
+/// Render an image in the terminal, followed by its metadata
+pub fn preview_image(
+    roots: &[ImageRoot],
+    identifier: &OsString,
+    size: Option<(u32, u32)>,
+    protocol: PreviewProtocol,
+) -> Result<()> {
+    let identifier = identifier.to_string_lossy();
+    let metas = utils::common::load_root_metas(roots)?;
+    let image_path = utils::common::resolve_image_path(&identifier, &metas)?;
+    let meta = metas.iter().find(|meta| meta.path == image_path);
+
+    let (columns, rows) = size.unwrap_or_else(get_preview_size);
+    let info_lines = match meta {
+        Some(meta) => get_info_lines(meta),
+        None => vec![image_path.display().to_string()],
+    };
+    let image_rows = rows.saturating_sub(info_lines.len() as u32 + 1).max(1);
+
+    let output = render_image(&image_path, columns, image_rows, protocol)?;
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(output.as_bytes())?;
+    for line in info_lines.iter() {
+        writeln!(stdout, "{line}")?;
+    }
+
+    Ok(())
+}
+
+/// Render an image to fit in the given number of cells, as escape sequences for the terminal
+pub fn render_image(
+    image_path: &Path,
+    columns: u32,
+    rows: u32,
+    protocol: PreviewProtocol,
+) -> Result<String> {
+    let protocol = resolve_protocol(protocol);
+    info!("previewing with protocol: {:?}", protocol);
+
+    let output = match protocol {
+        PreviewProtocol::Kitty => {
+            let image = load_preview_image(image_path, columns * CELL_WIDTH, rows * CELL_HEIGHT)?;
+            let image_columns = image.width().div_ceil(CELL_WIDTH);
+            let image_rows = image.height().div_ceil(CELL_HEIGHT);
+            let encoded =
+                utils::graphics::encode_kitty(&image.to_rgba8(), image_columns, image_rows)?;
+            format!("{encoded}\n")
+        }
+        PreviewProtocol::Sixel => {
+            let image = load_preview_image(image_path, columns * CELL_WIDTH, rows * CELL_HEIGHT)?;
+            utils::graphics::encode_sixel(&image.to_rgba8())?
+        }
+        PreviewProtocol::Blocks | PreviewProtocol::Auto => {
+            let image = load_preview_image(image_path, columns, rows * 2)?;
+            utils::graphics::encode_half_blocks(&image.to_rgba8())?
+        }
+    };
+
+    Ok(output)
+}
+
+/// Clear the terminal, including the images drawn with the kitty graphics protocol
+pub fn clear_screen(protocol: PreviewProtocol) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    if protocol == PreviewProtocol::Kitty {
+        stdout.write_all(utils::graphics::KITTY_DELETE_ALL.as_bytes())?;
+    }
+
+    execute!(
+        stdout,
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0)
+    )?;
+    Ok(())
+}
+
+/// Wait for a single key press, with the terminal in raw mode only meanwhile
+pub fn read_key() -> Result<KeyCode> {
+    terminal::enable_raw_mode()?;
+    let key = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => break Ok(key),
+            Ok(_) => continue,
+            Err(e) => break Err(e),
+        }
+    };
+    terminal::disable_raw_mode()?;
+
+    let key = key?;
+    match (key.code, key.modifiers.contains(KeyModifiers::CONTROL)) {
+        (KeyCode::Char('c'), true) => Ok(KeyCode::Esc),
+        (code, _) => Ok(code),
+    }
+}
+
+/// Size of the preview in cells: the fzf preview window, the terminal, or a default size
+fn get_preview_size() -> (u32, u32) {
+    let get_var = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| env::var(name).ok()?.parse::<u32>().ok())
+    };
+
+    let columns = get_var(&["FZF_PREVIEW_COLUMNS", "COLUMNS"]).unwrap_or(DEFAULT_COLUMNS);
+    let rows = get_var(&["FZF_PREVIEW_LINES", "LINES"]).unwrap_or(DEFAULT_ROWS);
+    (columns, rows)
+}
+
+/// Get the protocol to render images with, guessing it when none was chosen
+pub fn resolve_protocol(protocol: PreviewProtocol) -> PreviewProtocol {
+    match protocol {
+        PreviewProtocol::Auto => detect_protocol(),
+        protocol => protocol,
+    }
+}
+
+/// Guess the graphics protocol supported by the terminal from its environment variables
+fn detect_protocol() -> PreviewProtocol {
+    let term = env::var("TERM").unwrap_or_default();
+    let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+    debug!("TERM: {term}, TERM_PROGRAM: {term_program}");
+
+    let is_kitty = env::var_os("KITTY_WINDOW_ID").is_some()
+        || term.contains("kitty")
+        || term.contains("ghostty")
+        || ["ghostty", "WezTerm"].contains(&term_program.as_str());
+
+    let is_sixel = ["foot", "mlterm", "contour", "yaft"]
+        .iter()
+        .any(|name| term.contains(name))
+        || term_program == "iTerm.app";
+
+    match (is_kitty, is_sixel) {
+        (true, _) => PreviewProtocol::Kitty,
+        (false, true) => PreviewProtocol::Sixel,
+        (false, false) => PreviewProtocol::Blocks,
+    }
+}
+
+/// Decode the image from its thumbnail when it is large enough, and fit it in the given box
+pub fn load_preview_image(
+    image_path: &Path,
+    max_width: u32,
+    max_height: u32,
+) -> Result<DynamicImage> {
+    let max_side = max_width.max(max_height);
+    let thumbnail_size = ThumbnailSize::ALL
+        .into_iter()
+        .find(|size| size.pixels() >= max_side)
+        .unwrap_or(ThumbnailSize::XLarge);
+
+    let source = match utils::thumbnail::get_or_create_thumbnail(image_path, thumbnail_size) {
+        Ok(thumbnail_path) => thumbnail_path,
+        Err(e) => {
+            warn!("failed to get thumbnail, decoding original image: {e}");
+            image_path.to_path_buf()
+        }
+    };
+
+    let image = ImageReader::open(&source)?
+        .with_guessed_format()?
+        .decode()?;
+    Ok(image.resize(max_width, max_height, FilterType::Triangle))
+}
+
+/// Title, resolution, scores and tags of an image, printed under its preview
+pub fn get_info_lines(meta: &ImageMeta) -> Vec<String> {
+    let mut lines = vec![format!("{} ({}x{})", meta.title, meta.width, meta.height)];
+
+    if !meta.scores.is_empty() {
+        let scores: Vec<_> = meta
+            .scores
+            .iter()
+            .map(|score| format!("{}: {}", score.name, score.value))
+            .collect();
+        lines.push(format!("scores: {}", scores.join(", ")));
+    }
+
+    if !meta.tags.is_empty() {
+        lines.push(format!("tags: {}", meta.tags.join(", ")));
+    }
+
+    lines
+}
@@ -0,0 +1,207 @@
+use anyhow::{ensure, Context, Result};
+use log::info;
+use ratatui::crossterm::{event::KeyCode, terminal};
+use std::{
+    io::{self, IsTerminal, Write},
+    ops::RangeInclusive,
+};
+
+use super::{args::FilterArgs, list, preview, PreviewProtocol, RateOrder};
+use crate::{
+    models::{Configuration, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Lines around the image: the header, the prompt and a message
+const RESERVED_ROWS: u32 = 3;
+
+/// Answer given to an image of the session
+enum Answer {
+    Rated,
+    Skipped,
+}
+
+/// Show the images that have no value for the score one by one, and save the digit typed for each
+pub fn rate_images(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
+    score: &str,
+    order: RateOrder,
+    protocol: PreviewProtocol,
+) -> Result<()> {
+    ensure!(!score.is_empty(), "score name should not be empty");
+    configuration.ensure_score_is_defined(score)?;
+    let range = configuration.get_score_range(score).unwrap_or(0..=9);
+    ensure!(
+        *range.end() <= 9,
+        "score `{score}` goes up to {}, but only single digits can be typed, use `duel` or `metadata import` instead",
+        range.end()
+    );
+    ensure!(
+        io::stdin().is_terminal() && io::stdout().is_terminal(),
+        "rating images requires an interactive terminal"
+    );
+
+    let metas = utils::common::load_root_metas(roots)?;
+    let mut metas = list::filter_metas(roots, metas, filters)?;
+    metas.retain(|meta| !meta.scores.iter().any(|other| other.name == score));
+    match order {
+        RateOrder::Random => fastrand::shuffle(&mut metas),
+        RateOrder::Path => metas.sort_by(|a, b| a.path.cmp(&b.path)),
+    }
+
+    if metas.is_empty() {
+        println!("no image left to rate for `{score}`");
+        return Ok(());
+    }
+
+    let protocol = preview::resolve_protocol(protocol);
+    let keys = get_rating_keys(configuration, score, &range);
+    let mut answers = vec![];
+    let mut message = None;
+    while answers.len() < metas.len() {
+        let meta = &metas[answers.len()];
+        show_image(
+            meta,
+            answers.len(),
+            metas.len(),
+            score,
+            &keys,
+            protocol,
+            message.take(),
+        )?;
+
+        match preview::read_key()? {
+            KeyCode::Char(digit @ '0'..='9') => {
+                let value = digit.to_digit(10).context("key should be a digit")? as u8;
+                if let Err(e) = configuration.validate_score(score, value) {
+                    message = Some(e.to_string());
+                    continue;
+                }
+
+                save_score(roots, meta, score, Some(value))?;
+                answers.push(Answer::Rated);
+            }
+            KeyCode::Char(' ' | 's') | KeyCode::Right => answers.push(Answer::Skipped),
+            KeyCode::Char('u') | KeyCode::Backspace | KeyCode::Left => match answers.pop() {
+                Some(Answer::Rated) => save_score(roots, &metas[answers.len()], score, None)?,
+                Some(Answer::Skipped) => {}
+                None => message = Some(String::from("nothing to undo")),
+            },
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            _ => message = Some(String::from("unknown key")),
+        }
+    }
+
+    preview::clear_screen(protocol)?;
+    let rated = answers
+        .iter()
+        .filter(|answer| matches!(answer, Answer::Rated))
+        .count();
+    println!(
+        "rated {} images, skipped {}, {} left without `{}`",
+        rated,
+        answers.len() - rated,
+        metas.len() - rated,
+        score
+    );
+
+    Ok(())
+}
+
+fn show_image(
+    meta: &ImageMeta,
+    position: usize,
+    total: usize,
+    score: &str,
+    keys: &str,
+    protocol: PreviewProtocol,
+    message: Option<String>,
+) -> Result<()> {
+    preview::clear_screen(protocol)?;
+
+    let (columns, rows) = terminal::size()?;
+    let info_lines = preview::get_info_lines(meta);
+    let image_rows = u32::from(rows)
+        .saturating_sub(info_lines.len() as u32 + RESERVED_ROWS)
+        .max(1);
+
+    let mut stdout = io::stdout().lock();
+    writeln!(
+        stdout,
+        "[{}/{}] {}",
+        position + 1,
+        total,
+        meta.path.display()
+    )?;
+    match preview::render_image(&meta.path, u32::from(columns), image_rows, protocol) {
+        Ok(output) => stdout.write_all(output.as_bytes())?,
+        Err(e) => writeln!(stdout, "failed to render image: {e}")?,
+    }
+
+    for line in info_lines.iter() {
+        writeln!(stdout, "{line}")?;
+    }
+
+    if let Some(message) = message {
+        writeln!(stdout, "{message}")?;
+    }
+
+    write!(stdout, "{score}: {keys} rate, space skip, u undo, q quit ")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Describe the digits that can be typed for the score, with the meaning given to them
+fn get_rating_keys(
+    configuration: &Configuration,
+    score: &str,
+    range: &RangeInclusive<u8>,
+) -> String {
+    let (start, end) = (*range.start(), *range.end());
+    let labels: Vec<String> = range
+        .clone()
+        .filter_map(|value| {
+            let label = configuration.get_score_label(score, value)?;
+            Some(format!("{value} {label}"))
+        })
+        .collect();
+
+    match labels.is_empty() {
+        true => format!("{start}-{end}"),
+        false => format!("{start}-{end} ({})", labels.join(", ")),
+    }
+}
+
+/// Set the score of an image in its metadata file, or remove it when there is no value
+fn save_score(roots: &[ImageRoot], meta: &ImageMeta, score: &str, value: Option<u8>) -> Result<()> {
+    let root = roots
+        .iter()
+        .find(|root| meta.root.as_ref() == Some(&root.name))
+        .context(format!("no root contains: {}", meta.path.display()))?;
+
+    utils::common::update_image_metas(&root.metadata_path, |metas| {
+        let stored = metas
+            .iter_mut()
+            .find(|stored| stored.id == meta.id && stored.path == meta.path)
+            .context(format!(
+                "metadata was removed from file: {}",
+                meta.path.display()
+            ))?;
+
+        match value {
+            Some(value) => stored.set_score(score, value),
+            None => stored.scores.retain(|other| other.name != score),
+        }
+
+        Ok(())
+    })?;
+
+    match value {
+        Some(value) => info!("rated {}: {} = {}", meta.path.display(), score, value),
+        None => info!("removed {} from: {}", score, meta.path.display()),
+    }
+
+    Ok(())
+}
@@ -1,22 +1,36 @@
 use anyhow::Result;
 use log::{debug, info, warn};
 use serde_json::json;
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-use crate::{models::ImageMeta, utils};
+use crate::{
+    models::{ConfigurationWalker, DecodeStatus, ImageMeta, ImageRoot},
+    utils,
+};
 
 pub fn scan_images(
-    base_directory: &Path,
-    metadata_path: &Path,
+    roots: &[ImageRoot],
+    walker: &ConfigurationWalker,
+    deep: bool,
     use_json_format: bool,
 ) -> Result<()> {
     info!("scanning for missing metadata or images...");
+    let all_metas = utils::common::load_root_metas(roots)?;
 
-    info!("about to run WalkDir on {}", base_directory.display());
-    let all_metas = utils::common::load_image_metas(metadata_path)?;
+    let mut images = vec![];
+    let mut image_roots: HashMap<PathBuf, &str> = HashMap::new();
+    for root in roots.iter() {
+        info!("about to walk through {}", root.path.display());
+        for image_path in utils::common::get_root_images(root, walker)? {
+            image_roots.insert(image_path.clone(), &root.name);
+            images.push(image_path);
+        }
+    }
 
     let mut mappings: HashMap<&Path, Option<ImageMeta>> = HashMap::new();
-    let images = utils::common::get_all_images(base_directory)?;
     for image_path in images.iter() {
         let matching_meta = all_metas
             .iter()
@@ -28,47 +42,135 @@ pub fn scan_images(
 
     debug!("created {} img:Option<meta> mappings", mappings.len());
 
-    let mut metaless_images: HashMap<String, &Path> = HashMap::new();
+    // Identical files share their hash, so each hash maps to every copy without metadata
+    let mut metaless_images: HashMap<String, Vec<&Path>> = HashMap::new();
+    let mut known_hashes: HashMap<&Path, String> = HashMap::new();
+    let mut metaless_count = 0;
     for (img_path, metadata) in mappings.iter() {
-        if metadata.is_none() {
-            let hash = utils::common::compute_blake3_hash(img_path)?;
-            metaless_images.insert(hash, img_path);
+        match metadata {
+            Some(metadata) => {
+                known_hashes.insert(img_path, metadata.id.clone());
+            }
+            None => {
+                let hash = match utils::common::compute_blake3_hash(img_path) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        warn!("cannot read {}: {e}", img_path.display());
+                        continue;
+                    }
+                };
+
+                metaless_images
+                    .entry(hash.clone())
+                    .or_default()
+                    .push(img_path);
+                known_hashes.insert(img_path, hash);
+                metaless_count += 1;
+            }
         }
     }
 
-    debug!(
-        "computed hash for {} images that had no metadata",
-        metaless_images.len()
-    );
-
-    let mut moved_images: HashMap<&Path, &ImageMeta> = HashMap::new();
-    let mut deleted_images: Vec<&ImageMeta> = vec![];
+    debug!("computed hash for {metaless_count} images that had no metadata");
 
+    let mut missing_metas: Vec<(&str, Vec<&ImageMeta>)> = vec![];
     for meta in all_metas.iter() {
         if meta.path.exists() {
             continue;
         }
 
         warn!("image path invalid for: {meta:?}");
-        if let Some(image_path) = metaless_images.get(&meta.id) {
+        match missing_metas.iter_mut().find(|(id, _)| *id == meta.id) {
+            Some((_, metas)) => metas.push(meta),
+            None => missing_metas.push((&meta.id, vec![meta])),
+        }
+    }
+
+    let mut moved_images: Vec<(&Path, &ImageMeta)> = vec![];
+    let mut deleted_images: Vec<&ImageMeta> = vec![];
+
+    for (id, metas) in missing_metas.into_iter() {
+        let candidates = metaless_images.remove(id).unwrap_or_default();
+        let (moved, deleted, unmatched) = pair_moved_images(metas, candidates);
+
+        for (image_path, meta) in moved.iter() {
             warn!(
                 "{} seems to have been moved to: {}",
                 meta.path.display(),
                 image_path.display()
             );
-            moved_images.insert(image_path, meta);
-            metaless_images.retain(|hash, _| *hash != meta.id);
-        } else {
+        }
+        for meta in deleted.iter() {
             warn!("cannot find image: {}", meta.path.display());
-            deleted_images.push(meta);
         }
+
+        moved_images.extend(moved);
+        deleted_images.extend(deleted);
+        if !unmatched.is_empty() {
+            metaless_images.insert(id.to_owned(), unmatched);
+        }
+    }
+
+    let mut new_images: Vec<&Path> = metaless_images.into_values().flatten().collect();
+    new_images.sort();
+
+    // Images are not hashed twice, and those with metadata are known by their id
+    let duplicate_groups = utils::common::find_identical_files(&images, &known_hashes)?;
+    debug!("found {} groups of duplicates", duplicate_groups.len());
+
+    let mut mismatched_images = vec![];
+    for image_path in images.iter() {
+        let extension_format = utils::common::get_extension_format(image_path);
+        let content_format = match utils::common::sniff_image_format(image_path) {
+            Ok(content_format) => content_format,
+            Err(e) => {
+                warn!("cannot read {}: {e}", image_path.display());
+                continue;
+            }
+        };
+
+        if extension_format != content_format {
+            warn!(
+                "extension of {} does not match its content",
+                image_path.display()
+            );
+            mismatched_images.push((image_path, extension_format, content_format));
+        }
+    }
+
+    let mut undecodable_images = vec![];
+    if deep {
+        info!("decoding {} images...", images.len());
+        let mut cache = utils::common::load_decode_cache()?;
+
+        for image_path in images.iter() {
+            let result = utils::common::check_image_decodes(image_path, &mut cache)?;
+            if result.status != DecodeStatus::Ok {
+                warn!("failed to decode {}", image_path.display());
+                undecodable_images.push((image_path, result));
+            }
+        }
+
+        cache.entries.retain(|path, _| path.exists());
+        utils::common::save_decode_cache(&cache)?;
     }
 
-    let new_images = metaless_images;
+    let show_roots = roots.len() > 1;
+    let root_prefix = |root: Option<&str>| match (show_roots, root) {
+        (true, Some(root)) => format!("[{root}] "),
+        _ => String::new(),
+    };
 
     match use_json_format {
         true => {
-            let new_images: Vec<_> = new_images.values().collect();
+            let new_images: Vec<_> = new_images
+                .iter()
+                .map(|img_path| {
+                    json!({
+                        "path": img_path,
+                        "root": image_roots.get(*img_path)
+                    })
+                })
+                .collect();
             let moved_images: Vec<_> = moved_images
                 .iter()
                 .map(|(new_path, meta)| {
@@ -79,10 +181,47 @@ pub fn scan_images(
                 })
                 .collect();
 
+            let duplicate_groups: Vec<_> = duplicate_groups
+                .iter()
+                .map(|(id, paths)| {
+                    json!({
+                        "id": id,
+                        "paths": paths,
+                    })
+                })
+                .collect();
+
+            let mismatched_images: Vec<_> = mismatched_images
+                .iter()
+                .map(|(img_path, extension_format, content_format)| {
+                    json!({
+                        "path": img_path,
+                        "root": image_roots.get(*img_path),
+                        "extension_format": utils::common::get_format_name(*extension_format),
+                        "content_format": utils::common::get_format_name(*content_format),
+                    })
+                })
+                .collect();
+
+            let undecodable_images: Vec<_> = undecodable_images
+                .iter()
+                .map(|(img_path, result)| {
+                    json!({
+                        "path": img_path,
+                        "root": image_roots.get(*img_path),
+                        "status": result.status,
+                        "error": result.error,
+                    })
+                })
+                .collect();
+
             let summary = json!({
                 "new": new_images,
                 "moved": moved_images,
                 "deleted": deleted_images,
+                "duplicates": duplicate_groups,
+                "mismatched": mismatched_images,
+                "undecodable": undecodable_images,
             });
             let summary_json = serde_json::to_string(&summary)?;
             println!("{summary_json}");
@@ -90,8 +229,9 @@ pub fn scan_images(
         false => {
             if !new_images.is_empty() {
                 println!("{} new:", new_images.len());
-                for (_, img_path) in new_images.iter() {
-                    println!("- {}", img_path.display());
+                for img_path in new_images.iter() {
+                    let root = image_roots.get(*img_path).copied();
+                    println!("- {}{}", root_prefix(root), img_path.display());
                 }
                 println!();
             }
@@ -99,7 +239,12 @@ pub fn scan_images(
             if !moved_images.is_empty() {
                 println!("{} moved:", moved_images.len());
                 for (new_path, metadata) in moved_images.iter() {
-                    println!("- {} -> {}", metadata.path.display(), new_path.display())
+                    println!(
+                        "- {}{} -> {}",
+                        root_prefix(metadata.root.as_deref()),
+                        metadata.path.display(),
+                        new_path.display()
+                    )
                 }
                 println!();
             }
@@ -107,7 +252,54 @@ pub fn scan_images(
             if !deleted_images.is_empty() {
                 println!("{} deleted:", deleted_images.len());
                 for metadata in deleted_images.iter() {
-                    println!("- {}", metadata.path.display());
+                    println!(
+                        "- {}{}",
+                        root_prefix(metadata.root.as_deref()),
+                        metadata.path.display()
+                    );
+                }
+                println!();
+            }
+
+            if !duplicate_groups.is_empty() {
+                println!("{} duplicated:", duplicate_groups.len());
+                for (_, paths) in duplicate_groups.iter() {
+                    let root = image_roots.get(&paths[0]).copied();
+                    let paths: Vec<_> = paths
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect();
+                    println!("- {}{}", root_prefix(root), paths.join(" = "));
+                }
+                println!();
+            }
+
+            if !mismatched_images.is_empty() {
+                println!("{} mismatched:", mismatched_images.len());
+                for (img_path, extension_format, content_format) in mismatched_images.iter() {
+                    let root = image_roots.get(*img_path).copied();
+                    println!(
+                        "- {}{} (extension: {}, content: {})",
+                        root_prefix(root),
+                        img_path.display(),
+                        utils::common::get_format_name(*extension_format),
+                        utils::common::get_format_name(*content_format)
+                    );
+                }
+                println!();
+            }
+
+            if !undecodable_images.is_empty() {
+                println!("{} undecodable:", undecodable_images.len());
+                for (img_path, result) in undecodable_images.iter() {
+                    let root = image_roots.get(*img_path).copied();
+                    println!(
+                        "- {}{} ({}: {})",
+                        root_prefix(root),
+                        img_path.display(),
+                        result.status.name(),
+                        result.error.as_deref().unwrap_or_default()
+                    );
                 }
                 println!();
             }
@@ -116,3 +308,158 @@ pub fn scan_images(
 
     Ok(())
 }
+
+/// Match metadatas of missing files with identical images that have no metadata.
+/// Returns the moved images, the metadatas left without a file and the images left without metadata
+pub fn pair_moved_images<'a>(
+    mut metas: Vec<&'a ImageMeta>,
+    mut candidates: Vec<&'a Path>,
+) -> (
+    Vec<(&'a Path, &'a ImageMeta)>,
+    Vec<&'a ImageMeta>,
+    Vec<&'a Path>,
+) {
+    candidates.sort();
+    let mut moved = vec![];
+
+    // Prefer copies that kept their file name, then pair the remaining ones in order
+    metas.retain(|meta| {
+        let same_name = candidates
+            .iter()
+            .position(|candidate| candidate.file_name() == meta.path.file_name());
+
+        match same_name {
+            Some(index) => {
+                moved.push((candidates.remove(index), *meta));
+                false
+            }
+            None => true,
+        }
+    });
+
+    let paired_count = metas.len().min(candidates.len());
+    let remaining_metas = metas.split_off(paired_count);
+    let remaining_candidates = candidates.split_off(paired_count);
+    moved.extend(candidates.into_iter().zip(metas));
+
+    (moved, remaining_metas, remaining_candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(path: &str) -> ImageMeta {
+        serde_json::from_value(json!({
+            "id": path,
+            "path": path,
+            "title": "",
+            "description": "",
+            "width": 1,
+            "height": 1,
+            "scores": [],
+            "tags": [],
+            "colors": [],
+        }))
+        .unwrap()
+    }
+
+    fn moved_paths<'a>(moved: &[(&'a Path, &'a ImageMeta)]) -> Vec<(&'a Path, &'a Path)> {
+        moved
+            .iter()
+            .map(|(path, meta)| (*path, meta.path.as_path()))
+            .collect()
+    }
+
+    #[test]
+    fn pairs_copies_with_the_same_file_name_first() {
+        let metas = [meta("/old/b.png"), meta("/old/a.png")];
+        let candidates = [Path::new("/new/a.png"), Path::new("/new/b.png")];
+
+        let (moved, metas_left, candidates_left) =
+            pair_moved_images(metas.iter().collect(), candidates.to_vec());
+
+        let mut moved = moved_paths(&moved);
+        moved.sort();
+        assert_eq!(
+            moved,
+            vec![
+                (Path::new("/new/a.png"), Path::new("/old/a.png")),
+                (Path::new("/new/b.png"), Path::new("/old/b.png")),
+            ]
+        );
+        assert!(metas_left.is_empty());
+        assert!(candidates_left.is_empty());
+    }
+
+    #[test]
+    fn pairs_renamed_copies_in_order() {
+        let metas = [meta("/old/a.png"), meta("/old/b.png")];
+        let candidates = [Path::new("/new/d.png"), Path::new("/new/c.png")];
+
+        let (moved, metas_left, candidates_left) =
+            pair_moved_images(metas.iter().collect(), candidates.to_vec());
+
+        assert_eq!(
+            moved_paths(&moved),
+            vec![
+                (Path::new("/new/c.png"), Path::new("/old/a.png")),
+                (Path::new("/new/d.png"), Path::new("/old/b.png")),
+            ]
+        );
+        assert!(metas_left.is_empty());
+        assert!(candidates_left.is_empty());
+    }
+
+    #[test]
+    fn leaves_extra_metas_and_candidates_unpaired() {
+        let metas = [meta("/old/a.png"), meta("/old/b.png"), meta("/old/c.png")];
+        let candidates = [Path::new("/new/b.png")];
+
+        let (moved, metas_left, candidates_left) =
+            pair_moved_images(metas.iter().collect(), candidates.to_vec());
+
+        assert_eq!(
+            moved_paths(&moved),
+            vec![(Path::new("/new/b.png"), Path::new("/old/b.png"))]
+        );
+        let metas_left: Vec<&Path> = metas_left.iter().map(|meta| meta.path.as_path()).collect();
+        assert_eq!(
+            metas_left,
+            vec![Path::new("/old/a.png"), Path::new("/old/c.png")]
+        );
+        assert!(candidates_left.is_empty());
+
+        let metas = [meta("/old/a.png")];
+        let candidates = [
+            Path::new("/new/z.png"),
+            Path::new("/new/y.png"),
+            Path::new("/new/a.png"),
+        ];
+
+        let (moved, metas_left, candidates_left) =
+            pair_moved_images(metas.iter().collect(), candidates.to_vec());
+
+        assert_eq!(
+            moved_paths(&moved),
+            vec![(Path::new("/new/a.png"), Path::new("/old/a.png"))]
+        );
+        assert!(metas_left.is_empty());
+        assert_eq!(
+            candidates_left,
+            vec![Path::new("/new/y.png"), Path::new("/new/z.png")]
+        );
+    }
+
+    #[test]
+    fn pairs_nothing_without_candidates() {
+        let metas = [meta("/old/a.png")];
+
+        let (moved, metas_left, candidates_left) =
+            pair_moved_images(metas.iter().collect(), vec![]);
+
+        assert!(moved.is_empty());
+        assert_eq!(metas_left.len(), 1);
+        assert!(candidates_left.is_empty());
+    }
+}
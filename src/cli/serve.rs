@@ -0,0 +1,363 @@
+use anyhow::{anyhow, bail, Result};
+use clap::{CommandFactory, Parser};
+use log::{debug, info, warn};
+use std::{
+    ffi::OsString, fmt, fs::File, io::Read, net::SocketAddr, sync::mpsc, thread, time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use super::{args::FilterArgs, list, metadata};
+use crate::{
+    models::{Configuration, ImageMeta, MetadataPatch},
+    utils,
+};
+
+const OPENAPI_SPEC: &str = include_str!("../../openapi.json");
+
+/// Largest request body accepted, in bytes
+const MAX_BODY_SIZE: u64 = 1 << 20;
+
+/// Number of requests handled at the same time
+const WORKER_COUNT: usize = 8;
+
+/// Time given to a client to send the body of its request
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Query parameters of `GET /images`, parsed as the command line arguments of `kanumi list`
+#[derive(Debug, Parser)]
+#[command(name = "images", no_binary_name = true)]
+struct FilterQuery {
+    #[command(flatten)]
+    filters: FilterArgs,
+}
+
+/// Error returned to the client with the given HTTP status
+#[derive(Debug)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn api_error(status: u16, message: impl fmt::Display) -> anyhow::Error {
+    anyhow!(ApiError {
+        status,
+        message: message.to_string(),
+    })
+}
+
+enum Reply {
+    Json(String),
+    File(File, &'static str),
+}
+
+pub fn serve_api(
+    configuration: &Configuration,
+    bind: SocketAddr,
+    token: Option<String>,
+) -> Result<()> {
+    if token.is_none() && !bind.ip().is_loopback() {
+        warn!("listening on a public address without token: anyone can edit metadata");
+    }
+
+    let server = match Server::http(bind) {
+        Ok(server) => server,
+        Err(e) => bail!("failed to listen on {bind}: {e}"),
+    };
+    info!("listening on: http://{bind}");
+
+    // A slow client, or a large file being sent, only holds one of the workers
+    let token = token.as_deref();
+    thread::scope(|scope| {
+        for _ in 0..WORKER_COUNT {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    debug!("{} {}", request.method(), redact_token(request.url()));
+                    handle_request(configuration, token, request);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_request(configuration: &Configuration, token: Option<&str>, request: Request) {
+    if !is_authorized(&request, token) {
+        return respond(request, Err(api_error(401, "missing or invalid token")));
+    }
+
+    let (request, body) = match request.method() {
+        Method::Patch => match read_body(request) {
+            Some(read) => read,
+            None => return,
+        },
+        _ => (request, Ok(String::new())),
+    };
+
+    let result = body.and_then(|body| route_request(configuration, &request, &body));
+    respond(request, result);
+}
+
+/// Read the body of a request in its own thread, so that a client that stops sending holds that
+/// thread instead of a worker. The client gets a timeout error if it ever sends the rest
+fn read_body(mut request: Request) -> Option<(Request, Result<String>)> {
+    let url = redact_token(request.url());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut body = String::new();
+        let result = match request
+            .as_reader()
+            .take(MAX_BODY_SIZE + 1)
+            .read_to_string(&mut body)
+        {
+            Ok(_) if body.len() as u64 > MAX_BODY_SIZE => Err(api_error(
+                413,
+                format!("request body should not exceed {MAX_BODY_SIZE} bytes"),
+            )),
+            Ok(_) => Ok(body),
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(mpsc::SendError((request, _))) = sender.send((request, result)) {
+            respond(
+                request,
+                Err(api_error(408, "request body took too long to send")),
+            );
+        }
+    });
+
+    match receiver.recv_timeout(BODY_TIMEOUT) {
+        Ok(read) => Some(read),
+        Err(_) => {
+            warn!("PATCH {url}: request body took too long to send");
+            None
+        }
+    }
+}
+
+fn respond(request: Request, result: Result<Reply>) {
+    let json_header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = match result {
+        Ok(Reply::Json(json)) => {
+            request.respond(Response::from_string(json).with_header(json_header))
+        }
+        Ok(Reply::File(file, mime_type)) => {
+            let header = Header::from_bytes("Content-Type", mime_type).unwrap();
+            request.respond(Response::from_file(file).with_header(header))
+        }
+        Err(e) => {
+            let status = match e.downcast_ref::<ApiError>() {
+                Some(api_error) => api_error.status,
+                None => 500,
+            };
+
+            warn!(
+                "{} {}: {} {}",
+                request.method(),
+                redact_token(request.url()),
+                status,
+                e
+            );
+            let json = serde_json::json!({ "error": e.to_string() }).to_string();
+            let response = Response::from_string(json)
+                .with_status_code(status)
+                .with_header(json_header);
+            request.respond(response)
+        }
+    };
+
+    if let Err(e) = response {
+        warn!("failed to send response: {e}");
+    }
+}
+
+/// Whether the request carries the token, as a bearer token or as a `token` query parameter
+fn is_authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+
+    let bearer = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "));
+
+    let (_, query) = split_url(request.url());
+    let query_token = form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned());
+
+    // Comparing hashes takes the same time whatever the given token, unlike comparing strings
+    let is_token = |given: &str| blake3::hash(given.as_bytes()) == blake3::hash(token.as_bytes());
+    bearer.is_some_and(is_token) || query_token.as_deref().is_some_and(is_token)
+}
+
+fn split_url(url: &str) -> (&str, &str) {
+    url.split_once('?').unwrap_or((url, ""))
+}
+
+/// URL without the value of its `token` query parameter, to be logged
+fn redact_token(url: &str) -> String {
+    let (path, query) = split_url(url);
+    if query.is_empty() {
+        return path.to_owned();
+    }
+
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key == "token" {
+            true => serializer.append_pair(&key, "redacted"),
+            false => serializer.append_pair(&key, &value),
+        };
+    }
+
+    format!("{path}?{}", serializer.finish())
+}
+
+fn route_request(configuration: &Configuration, request: &Request, body: &str) -> Result<Reply> {
+    let url = request.url().to_owned();
+    let (path, query) = split_url(&url);
+    let query: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .filter(|(key, _)| key != "token")
+        .collect();
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["openapi.json"]) => Ok(Reply::Json(OPENAPI_SPEC.to_owned())),
+        (Method::Get, ["images"]) => list_images(configuration, &query),
+        (Method::Get, ["images", id]) => {
+            let meta = get_metadata(configuration, id)?;
+            Ok(Reply::Json(serde_json::to_string(&meta)?))
+        }
+        (Method::Patch, ["images", id]) => patch_metadata(configuration, id, body),
+        (Method::Get, ["images", id, "file"]) => get_image_file(configuration, id),
+        (Method::Get, ["search"]) => search_images(configuration, &query),
+        (_, ["openapi.json" | "images" | "search"] | ["images", _] | ["images", _, "file"]) => {
+            Err(api_error(405, "method not allowed"))
+        }
+        _ => Err(api_error(404, format!("not found: {path}"))),
+    }
+}
+
+/// List images using the same filters as `kanumi list`, given as query parameters
+fn list_images(configuration: &Configuration, query: &[(String, String)]) -> Result<Reply> {
+    let command = FilterQuery::command();
+    let mut args = vec![];
+    for (key, value) in query.iter() {
+        // Palette files would let clients read files of the server
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key) && key != "palette");
+
+        match arg {
+            Some(arg) if arg.get_action().takes_values() => args.push(format!("--{key}={value}")),
+            Some(_) if value.is_empty() || value == "true" => args.push(format!("--{key}")),
+            Some(_) if value == "false" => {}
+            Some(_) => return Err(api_error(400, format!("expected boolean for `{key}`"))),
+            None => return Err(api_error(400, format!("unknown query parameter: `{key}`"))),
+        }
+    }
+
+    let filters = match FilterQuery::try_parse_from(args) {
+        Ok(query) => query.filters,
+        Err(e) => {
+            let message = e.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            return Err(api_error(400, message.trim_start_matches("error: ")));
+        }
+    };
+
+    let filters = list::resolve_filters(filters, configuration).map_err(|e| api_error(400, e))?;
+    let roots = utils::common::get_image_roots(configuration, filters.root_name.as_deref())
+        .map_err(|e| api_error(400, e))?;
+
+    let metas = utils::common::load_root_metas(&roots)?;
+    let metas = list::filter_metas(&roots, metas, &filters).map_err(|e| api_error(400, e))?;
+    Ok(Reply::Json(serde_json::to_string(&metas)?))
+}
+
+fn get_metadata(configuration: &Configuration, id: &str) -> Result<ImageMeta> {
+    let roots = utils::common::get_image_roots(configuration, None)?;
+    let metas = utils::common::load_root_metas(&roots)?;
+
+    match metas.into_iter().find(|meta| meta.id == id) {
+        Some(meta) => Ok(meta),
+        None => Err(api_error(404, format!("no matching metadata for: {id}"))),
+    }
+}
+
+fn patch_metadata(configuration: &Configuration, id: &str, body: &str) -> Result<Reply> {
+    let patch: MetadataPatch =
+        serde_json::from_str(body).map_err(|e| api_error(400, format!("invalid body: {e}")))?;
+
+    patch
+        .validate(configuration)
+        .map_err(|e| api_error(400, e))?;
+
+    let mut meta = get_metadata(configuration, id)?;
+    let roots = utils::common::get_image_roots(configuration, None)?;
+    let root = roots
+        .iter()
+        .find(|root| meta.root.as_ref() == Some(&root.name))
+        .ok_or_else(|| anyhow!("meta should belong to a root"))?;
+
+    patch.apply(&mut meta);
+    utils::common::update_image_metas(&root.metadata_path, |metas| {
+        match metas
+            .iter_mut()
+            .find(|stored| stored.id == meta.id && stored.path == meta.path)
+        {
+            Some(stored) => patch.apply(stored),
+            None => return Err(api_error(404, format!("no matching metadata for: {id}"))),
+        }
+
+        Ok(())
+    })?;
+
+    info!("updated metadata: {}", meta.path.display());
+    Ok(Reply::Json(serde_json::to_string(&meta)?))
+}
+
+fn get_image_file(configuration: &Configuration, id: &str) -> Result<Reply> {
+    let meta = get_metadata(configuration, id)?;
+    let file = File::open(&meta.path)
+        .map_err(|e| api_error(404, format!("failed to open {}: {e}", meta.path.display())))?;
+
+    let format = utils::common::sniff_image_format(&meta.path)?
+        .or(utils::common::get_extension_format(&meta.path));
+    let mime_type = match format {
+        Some(format) => format.to_mime_type(),
+        None => "application/octet-stream",
+    };
+
+    Ok(Reply::File(file, mime_type))
+}
+
+/// Fuzzy search of the best matching image, as with `kanumi metadata search`
+fn search_images(configuration: &Configuration, query: &[(String, String)]) -> Result<Reply> {
+    let search = query
+        .iter()
+        .find(|(key, _)| key == "q")
+        .map(|(_, value)| value.as_str())
+        .ok_or_else(|| api_error(400, "missing query parameter: `q`"))?;
+
+    let roots = utils::common::get_image_roots(configuration, None)?;
+    let metas = utils::common::load_root_metas(&roots)?;
+    let result = metadata::search_metadata(&roots, OsString::from(search), &metas)
+        .map_err(|e| api_error(400, e))?;
+
+    match result {
+        Some(meta) => Ok(Reply::Json(serde_json::to_string(&meta)?)),
+        None => Err(api_error(404, format!("no match for: {search}"))),
+    }
+}
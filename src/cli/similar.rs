@@ -0,0 +1,172 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    models::{ConfigurationWalker, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Image found in the roots, along with its perceptual hash
+#[derive(Debug)]
+pub struct PerceptualImage {
+    pub path: PathBuf,
+    pub hash: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn find_similar_images(
+    roots: &[ImageRoot],
+    walker: &ConfigurationWalker,
+    identifier: &OsString,
+    threshold: u32,
+    use_json_format: bool,
+) -> Result<()> {
+    let identifier = identifier.to_string_lossy();
+    let metas = utils::common::load_root_metas(roots)?;
+    let target_path = utils::common::resolve_image_path(&identifier, &metas)?;
+
+    let images = load_perceptual_images(roots, walker)?;
+    let target_hash = match images.iter().find(|image| image.path == target_path) {
+        Some(image) => image.hash,
+        None => utils::common::compute_perceptual_hash(&target_path)?,
+    };
+    debug!(
+        "perceptual hash of {}: {}",
+        target_path.display(),
+        utils::common::format_perceptual_hash(target_hash)
+    );
+
+    let mut similar_images: Vec<_> = images
+        .iter()
+        .filter(|image| image.path != target_path)
+        .map(|image| {
+            let distance = utils::common::get_hash_distance(target_hash, image.hash);
+            (image, distance)
+        })
+        .filter(|(_, distance)| *distance <= threshold)
+        .collect();
+    similar_images.sort_by(|(a, a_distance), (b, b_distance)| {
+        a_distance.cmp(b_distance).then(a.path.cmp(&b.path))
+    });
+
+    match use_json_format {
+        true => {
+            let similar_images: Vec<_> = similar_images
+                .iter()
+                .map(|(image, distance)| {
+                    json!({
+                        "path": image.path,
+                        "distance": distance,
+                        "width": image.width,
+                        "height": image.height,
+                    })
+                })
+                .collect();
+            let similar_json = serde_json::to_string(&similar_images)?;
+            println!("{similar_json}");
+        }
+        false => {
+            for (image, _) in similar_images.iter() {
+                println!("{}", image.path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the perceptual hash of every image in the roots. Hashes are read from metadata when
+/// available and the image did not change since, otherwise they are computed and saved to the
+/// metadata of the image if it has one
+pub fn load_perceptual_images(
+    roots: &[ImageRoot],
+    walker: &ConfigurationWalker,
+) -> Result<Vec<PerceptualImage>> {
+    let metas = utils::common::load_root_metas(roots)?;
+    let metas_by_path: HashMap<&Path, &ImageMeta> = metas
+        .iter()
+        .map(|meta| (meta.path.as_path(), meta))
+        .collect();
+
+    let mut images = vec![];
+    let mut seen_images = HashSet::new();
+    let mut computed_hashes: Vec<(String, PathBuf, String)> = vec![];
+
+    for root in roots.iter() {
+        info!("about to walk through {}", root.path.display());
+        for image_path in utils::common::get_root_images(root, walker)? {
+            if !seen_images.insert(image_path.clone()) {
+                continue;
+            }
+
+            // Metadata of an image whose content changed since does not describe it anymore
+            let meta = metas_by_path.get(image_path.as_path()).filter(|meta| {
+                utils::common::compute_blake3_hash(&image_path).is_ok_and(|hash| hash == meta.id)
+            });
+            let stored_hash = meta.and_then(|meta| meta.perceptual_hash.as_deref());
+            let image = match (meta, stored_hash) {
+                (Some(meta), Some(hash)) => PerceptualImage {
+                    hash: utils::common::parse_perceptual_hash(hash)?,
+                    width: meta.width,
+                    height: meta.height,
+                    path: image_path,
+                },
+                _ => match hash_image(&image_path) {
+                    Ok(image) => {
+                        if let Some(meta) = meta {
+                            let hash = utils::common::format_perceptual_hash(image.hash);
+                            computed_hashes.push((meta.id.clone(), meta.path.clone(), hash));
+                        }
+                        image
+                    }
+                    Err(e) => {
+                        warn!("failed to hash {}: {}", image_path.display(), e);
+                        continue;
+                    }
+                },
+            };
+
+            images.push(image);
+        }
+    }
+
+    if !computed_hashes.is_empty() {
+        debug!("saving {} perceptual hashes", computed_hashes.len());
+        for metadata_path in utils::common::get_metadata_paths(roots) {
+            utils::common::update_image_metas(metadata_path, |metas| {
+                for meta in metas.iter_mut() {
+                    let computed = computed_hashes
+                        .iter()
+                        .find(|(id, path, _)| *id == meta.id && *path == meta.path);
+
+                    if let Some((_, _, hash)) = computed {
+                        meta.perceptual_hash = Some(hash.clone());
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(images)
+}
+
+fn hash_image(image_path: &Path) -> Result<PerceptualImage> {
+    let hash = utils::common::compute_perceptual_hash(image_path)?;
+    let (width, height) = utils::common::get_image_dims(image_path)?;
+
+    Ok(PerceptualImage {
+        path: image_path.to_path_buf(),
+        hash,
+        width,
+        height,
+    })
+}
@@ -0,0 +1,73 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+use std::ffi::OsString;
+
+use super::{args::FilterArgs, list};
+use crate::{
+    models::{ImageRoot, ThumbnailSize},
+    utils,
+};
+
+/// Print the path of the thumbnail of an image, creating it if needed
+pub fn print_thumbnail(
+    roots: &[ImageRoot],
+    identifier: &OsString,
+    size: ThumbnailSize,
+) -> Result<()> {
+    let identifier = identifier.to_string_lossy();
+    let metas = utils::common::load_root_metas(roots)?;
+    let image = utils::common::resolve_image_path(&identifier, &metas)?;
+
+    let thumbnail_path = utils::thumbnail::get_or_create_thumbnail(&image, size)?;
+    println!("{}", thumbnail_path.display());
+    Ok(())
+}
+
+/// Create the missing thumbnails of the images matching the filters
+pub fn generate_thumbnails(
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
+    size: ThumbnailSize,
+) -> Result<()> {
+    let metas = utils::common::load_root_metas(roots)?;
+    let metas = list::filter_metas(roots, metas, filters)?;
+
+    info!(
+        "generating {} thumbnails of {} images...",
+        size.name(),
+        metas.len()
+    );
+    let (mut created, mut existing, mut failed) = (0, 0, 0);
+    for meta in metas.iter() {
+        let result = match utils::thumbnail::find_thumbnail(&meta.path, size) {
+            Ok(Some(_)) => {
+                existing += 1;
+                continue;
+            }
+            Ok(None) => utils::thumbnail::create_thumbnail(&meta.path, size),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(thumbnail_path) => {
+                info!("{} -> {}", meta.path.display(), thumbnail_path.display());
+                created += 1;
+            }
+            Err(e) => {
+                warn!(
+                    "failed to create thumbnail of {}: {}",
+                    meta.path.display(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{created} created, {existing} up to date, {failed} failed");
+    if failed > 0 {
+        bail!("failed to create {failed} thumbnails");
+    }
+
+    Ok(())
+}
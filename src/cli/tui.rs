@@ -0,0 +1,618 @@
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use image::RgbaImage;
+use log::{info, LevelFilter};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{self, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph, Widget, Wrap},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use super::{args::FilterArgs, list, preview};
+use crate::{
+    models::{Configuration, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Number of images skipped by page up and page down
+const PAGE_SIZE: usize = 10;
+
+/// How often to check for previews decoded in the background while no key is pressed
+const PREVIEW_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Filters typed in the TUI, parsed as the command line arguments of `kanumi list`
+#[derive(Debug, Parser)]
+#[command(name = "filter", no_binary_name = true)]
+struct FilterLine {
+    #[command(flatten)]
+    filters: FilterArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    Filter,
+    Score,
+    Tag,
+    Title,
+    Description,
+}
+
+impl PromptKind {
+    fn label(&self) -> &'static str {
+        match self {
+            PromptKind::Filter => "filter",
+            PromptKind::Score => "score name",
+            PromptKind::Tag => "toggle tag",
+            PromptKind::Title => "title",
+            PromptKind::Description => "description",
+        }
+    }
+}
+
+/// Line of text being typed at the bottom of the screen
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+/// Image to decode for the preview, with the size of the area to fit it in
+type PreviewRequest = (PathBuf, (u16, u16));
+
+/// Decoded preview of the selected image, fitted to the area it was drawn in
+struct PreviewCache {
+    path: PathBuf,
+    area: (u16, u16),
+    image: Result<RgbaImage, String>,
+}
+
+struct App<'a> {
+    configuration: &'a Configuration,
+    filters: FilterArgs,
+    filter_line: Option<String>,
+    roots: Vec<ImageRoot>,
+    metas: Vec<ImageMeta>,
+    list_state: ListState,
+    score_names: Vec<String>,
+    score_index: usize,
+    prompt: Option<Prompt>,
+    message: Option<Result<String, String>>,
+    preview: Option<PreviewCache>,
+    requested_preview: Option<PreviewRequest>,
+    /// Images to decode by the preview thread, so that scrolling never waits for decoding
+    preview_requests: Sender<PreviewRequest>,
+    decoded_previews: Receiver<PreviewCache>,
+    should_quit: bool,
+}
+
+/// Browse the images matching the filters in a full-screen terminal UI, to rate and tag them
+pub fn run_tui(configuration: &Configuration, filters: FilterArgs) -> Result<()> {
+    let mut app = App::new(configuration, filters)?;
+
+    // Logs written to stderr would be drawn over the UI, errors are shown in the status line instead
+    let max_level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+
+    log::set_max_level(max_level);
+    result
+}
+
+/// Decode the requested previews until the app stops, skipping those already replaced by a newer one
+fn decode_previews(requests: Receiver<PreviewRequest>, previews: Sender<PreviewCache>) {
+    while let Ok(mut request) = requests.recv() {
+        while let Ok(newer_request) = requests.try_recv() {
+            request = newer_request;
+        }
+
+        let (path, area) = request;
+        let image = preview::load_preview_image(&path, u32::from(area.0), u32::from(area.1) * 2)
+            .map(|image| image.to_rgba8())
+            .map_err(|e| e.to_string());
+
+        if previews.send(PreviewCache { path, area, image }).is_err() {
+            break;
+        }
+    }
+}
+
+impl<'a> App<'a> {
+    fn new(configuration: &'a Configuration, filters: FilterArgs) -> Result<App<'a>> {
+        let (preview_requests, requests) = mpsc::channel();
+        let (previews, decoded_previews) = mpsc::channel();
+        thread::spawn(move || decode_previews(requests, previews));
+
+        let mut app = App {
+            configuration,
+            filters,
+            filter_line: None,
+            roots: vec![],
+            metas: vec![],
+            list_state: ListState::default(),
+            score_names: vec![configuration.daemon.score.clone()],
+            score_index: 0,
+            prompt: None,
+            message: None,
+            preview: None,
+            requested_preview: None,
+            preview_requests,
+            decoded_previews,
+            should_quit: false,
+        };
+
+        app.reload()?;
+        Ok(app)
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.should_quit {
+            terminal.draw(|frame| self.render(frame))?;
+
+            if event::poll(PREVIEW_POLL_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+
+            while let Ok(preview) = self.decoded_previews.try_recv() {
+                self.preview = Some(preview);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load metadata again and apply the filters, keeping the selected image when it still matches
+    fn reload(&mut self) -> Result<()> {
+        let filters = list::resolve_filters(self.filters.clone(), self.configuration)?;
+        let roots =
+            utils::common::get_image_roots(self.configuration, filters.root_name.as_deref())?;
+        let library = utils::common::load_root_metas(&roots)?;
+
+        let selected_name = self.get_score_name().to_owned();
+        // Only the defined scores can be rated, when any is defined
+        self.score_names = match self.configuration.scores.is_empty() {
+            true => {
+                let mut score_names: BTreeSet<String> = library
+                    .iter()
+                    .flat_map(|meta| meta.scores.iter().map(|score| score.name.clone()))
+                    .collect();
+                score_names.extend(self.score_names.drain(..));
+                score_names.into_iter().collect()
+            }
+            false => self.configuration.scores.keys().cloned().collect(),
+        };
+        self.score_index = self
+            .score_names
+            .iter()
+            .position(|name| *name == selected_name)
+            .unwrap_or_default();
+
+        let selected_path = self.get_selected().map(|meta| meta.path.clone());
+        self.metas = list::filter_metas(&roots, library, &filters)?;
+        self.roots = roots;
+
+        let selected = selected_path
+            .and_then(|path| self.metas.iter().position(|meta| meta.path == path))
+            .or((!self.metas.is_empty()).then_some(0));
+        self.list_state.select(selected);
+
+        info!("loaded {} images", self.metas.len());
+        Ok(())
+    }
+
+    fn get_selected(&self) -> Option<&ImageMeta> {
+        self.metas.get(self.list_state.selected()?)
+    }
+
+    fn get_score_name(&self) -> &str {
+        &self.score_names[self.score_index]
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.should_quit = true;
+            return;
+        }
+
+        match self.prompt.take() {
+            Some(prompt) => self.handle_prompt_key(prompt, key),
+            None => {
+                self.message = None;
+                if let Err(e) = self.handle_normal_key(key) {
+                    self.message = Some(Err(e.to_string()));
+                }
+            }
+        }
+    }
+
+    fn handle_normal_key(&mut self, key: KeyEvent) -> Result<()> {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.should_quit = true,
+            KeyCode::Char('j') | KeyCode::Down => self.list_state.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.list_state.select_previous(),
+            KeyCode::Char('g') | KeyCode::Home => self.list_state.select_first(),
+            KeyCode::Char('G') | KeyCode::End => self.list_state.select_last(),
+            KeyCode::PageDown => self.list_state.scroll_down_by(PAGE_SIZE as u16),
+            KeyCode::PageUp => self.list_state.scroll_up_by(PAGE_SIZE as u16),
+            KeyCode::Char(digit @ '0'..='9') => {
+                let value = digit.to_digit(10).context("key should be a digit")? as u8;
+                self.set_score(value)?;
+            }
+            KeyCode::Char('x') => self.remove_score()?,
+            KeyCode::Char('s') => {
+                self.score_index = (self.score_index + 1) % self.score_names.len()
+            }
+            KeyCode::Char('S') => self.open_prompt(PromptKind::Score, String::new()),
+            KeyCode::Char('t') => self.open_prompt(PromptKind::Tag, String::new()),
+            KeyCode::Char('e') => {
+                let title = self.get_selected().map(|meta| meta.title.clone());
+                self.open_prompt(PromptKind::Title, title.unwrap_or_default());
+            }
+            KeyCode::Char('d') => {
+                let description = self.get_selected().map(|meta| meta.description.clone());
+                self.open_prompt(PromptKind::Description, description.unwrap_or_default());
+            }
+            KeyCode::Char('/') => {
+                let filter_line = self.filter_line.clone();
+                self.open_prompt(PromptKind::Filter, filter_line.unwrap_or_default());
+            }
+            KeyCode::Char('r') => {
+                self.reload()?;
+                self.preview = None;
+                self.requested_preview = None;
+                self.message = Some(Ok(format!("reloaded {} images", self.metas.len())));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind, input: String) {
+        self.prompt = Some(Prompt { kind, input });
+    }
+
+    fn handle_prompt_key(&mut self, mut prompt: Prompt, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc => return,
+            KeyCode::Enter => {
+                self.message = match self.submit_prompt(prompt.kind, prompt.input.trim()) {
+                    Ok(message) => Some(Ok(message)),
+                    Err(e) => Some(Err(e.to_string())),
+                };
+                return;
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.input.clear();
+            }
+            KeyCode::Char(c) => prompt.input.push(c),
+            _ => {}
+        }
+
+        self.prompt = Some(prompt);
+    }
+
+    fn submit_prompt(&mut self, kind: PromptKind, input: &str) -> Result<String> {
+        match kind {
+            PromptKind::Filter => self.apply_filter_line(input),
+            PromptKind::Score => {
+                ensure!(!input.is_empty(), "score name should not be empty");
+                self.configuration.ensure_score_is_defined(input)?;
+                self.score_index = match self.score_names.iter().position(|name| name == input) {
+                    Some(index) => index,
+                    None => {
+                        self.score_names.push(input.to_owned());
+                        self.score_names.len() - 1
+                    }
+                };
+                Ok(format!("rating `{input}`"))
+            }
+            PromptKind::Tag => {
+                ensure!(!input.is_empty(), "tag should not be empty");
+                let tag = input.to_owned();
+                let has_tag = self
+                    .get_selected()
+                    .is_some_and(|meta| meta.tags.contains(&tag));
+
+                self.edit_selected(|meta| match has_tag {
+                    true => meta.tags.retain(|other| *other != tag),
+                    false => meta.tags.push(tag.clone()),
+                })?;
+
+                match has_tag {
+                    true => Ok(format!("removed tag: {input}")),
+                    false => Ok(format!("added tag: {input}")),
+                }
+            }
+            PromptKind::Title => {
+                self.edit_selected(|meta| meta.title = input.to_owned())?;
+                Ok(String::from("saved title"))
+            }
+            PromptKind::Description => {
+                self.edit_selected(|meta| meta.description = input.to_owned())?;
+                Ok(String::from("saved description"))
+            }
+        }
+    }
+
+    /// Replace the filters given on the command line with the ones typed in the filter prompt
+    fn apply_filter_line(&mut self, input: &str) -> Result<String> {
+        let filters = match FilterLine::try_parse_from(input.split_whitespace()) {
+            Ok(line) => line.filters,
+            Err(e) => {
+                let message = e.to_string();
+                let message = message.lines().next().unwrap_or_default();
+                bail!("{}", message.trim_start_matches("error: "));
+            }
+        };
+
+        let previous_filters = std::mem::replace(&mut self.filters, filters);
+        if let Err(e) = self.reload() {
+            self.filters = previous_filters;
+            return Err(e);
+        }
+
+        self.filter_line = Some(input.to_owned());
+        Ok(format!("{} images match", self.metas.len()))
+    }
+
+    fn set_score(&mut self, value: u8) -> Result<()> {
+        self.configuration
+            .validate_score(self.get_score_name(), value)?;
+
+        let name = self.get_score_name().to_owned();
+        self.edit_selected(|meta| meta.set_score(&name, value))?;
+        self.message = Some(Ok(format!("{name} = {value}")));
+        Ok(())
+    }
+
+    fn remove_score(&mut self) -> Result<()> {
+        let name = self.get_score_name().to_owned();
+        self.edit_selected(|meta| meta.scores.retain(|score| score.name != name))?;
+        self.message = Some(Ok(format!("removed score: {name}")));
+        Ok(())
+    }
+
+    /// Apply a change to the selected image, in its metadata file first and then in memory
+    fn edit_selected<F>(&mut self, edit: F) -> Result<()>
+    where
+        F: Fn(&mut ImageMeta),
+    {
+        let index = self.list_state.selected().context("no image selected")?;
+        let meta = &self.metas[index];
+        let root = self
+            .roots
+            .iter()
+            .find(|root| meta.root.as_ref() == Some(&root.name))
+            .context(format!("no root contains: {}", meta.path.display()))?;
+
+        let (id, path) = (meta.id.clone(), meta.path.clone());
+        utils::common::update_image_metas(&root.metadata_path, |metas| {
+            let stored = metas
+                .iter_mut()
+                .find(|meta| meta.id == id && meta.path == path)
+                .context(format!(
+                    "metadata was removed from file: {}",
+                    path.display()
+                ))?;
+
+            edit(stored);
+            Ok(())
+        })?;
+
+        edit(&mut self.metas[index]);
+        info!("updated metadata: {}", path.display());
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [main_area, filter_area, status_area] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let [list_area, side_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main_area);
+
+        let [preview_area, meta_area] =
+            Layout::vertical([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(side_area);
+
+        self.render_list(frame, list_area);
+        self.render_preview(frame, preview_area);
+        self.render_metadata(frame, meta_area);
+
+        let filter_line = match &self.filter_line {
+            Some(line) => Line::from(vec!["filter: ".bold(), Span::raw(line.as_str())]),
+            None => Line::from(vec![
+                "filter: ".bold(),
+                "command line and profile filters, press / to change them".dark_gray(),
+            ]),
+        };
+        frame.render_widget(filter_line, filter_area);
+        self.render_status(frame, status_area);
+    }
+
+    fn render_list(&mut self, frame: &mut Frame, area: Rect) {
+        let score_name = self.get_score_name();
+        let items: Vec<ListItem> = self
+            .metas
+            .iter()
+            .map(|meta| {
+                let value = meta
+                    .scores
+                    .iter()
+                    .find(|score| score.name == score_name)
+                    .map(|score| score.value.to_string())
+                    .unwrap_or_else(|| String::from(" "));
+
+                let name = match meta.title.is_empty() {
+                    true => meta.path.file_name().unwrap_or_default().to_string_lossy(),
+                    false => meta.title.as_str().into(),
+                };
+
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("[{value}] ")).yellow(),
+                    Span::raw(name.into_owned()),
+                ]))
+            })
+            .collect();
+
+        let title = format!(" {} images ", self.metas.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+    }
+
+    fn render_preview(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Preview ");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let Some(path) = self.get_selected().map(|meta| meta.path.clone()) else {
+            return;
+        };
+
+        if inner.is_empty() {
+            return;
+        }
+
+        let request = (path, (inner.width, inner.height));
+        let preview = self
+            .preview
+            .as_ref()
+            .filter(|preview| (&preview.path, preview.area) == (&request.0, request.1));
+
+        if preview.is_none() && self.requested_preview.as_ref() != Some(&request) {
+            // The preview thread only stops when the app does
+            let _ = self.preview_requests.send(request.clone());
+            self.requested_preview = Some(request);
+        }
+
+        match preview.map(|preview| &preview.image) {
+            Some(Ok(image)) => frame.render_widget(HalfBlocks(image), inner),
+            Some(Err(e)) => frame.render_widget(
+                Paragraph::new(format!("failed to load preview: {e}"))
+                    .red()
+                    .wrap(Wrap { trim: true }),
+                inner,
+            ),
+            None => frame.render_widget(Paragraph::new("loading...").dark_gray(), inner),
+        }
+    }
+
+    fn render_metadata(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Metadata ");
+        let Some(meta) = self.get_selected() else {
+            frame.render_widget(Paragraph::new("no image matches").block(block), area);
+            return;
+        };
+
+        let score_name = self.get_score_name();
+        let mut scores: Vec<Span> = vec!["scores: ".bold()];
+        for score in meta.scores.iter() {
+            let span = match self.configuration.get_score_label(&score.name, score.value) {
+                Some(label) => Span::raw(format!("{}={} ({label}) ", score.name, score.value)),
+                None => Span::raw(format!("{}={} ", score.name, score.value)),
+            };
+            scores.push(match score.name == score_name {
+                true => span.yellow(),
+                false => span,
+            });
+        }
+
+        let theme = meta.theme.map(|theme| theme.name()).unwrap_or("unknown");
+        let lines = vec![
+            Line::from(meta.title.as_str().bold()),
+            Line::from(meta.description.as_str()),
+            Line::from(meta.path.display().to_string().dark_gray()),
+            Line::from(format!("{}x{}, {theme} theme", meta.width, meta.height)),
+            Line::from(scores),
+            Line::from(vec!["tags: ".bold(), Span::raw(meta.tags.join(", "))]),
+        ];
+
+        let paragraph = Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, area);
+    }
+
+    fn render_status(&self, frame: &mut Frame, area: Rect) {
+        let line = match (&self.prompt, &self.message) {
+            (Some(prompt), _) => Line::from(vec![
+                format!("{}: ", prompt.kind.label()).bold(),
+                Span::raw(prompt.input.as_str()),
+                "█".slow_blink(),
+            ]),
+            (None, Some(Ok(message))) => Line::from(message.as_str().green()),
+            (None, Some(Err(message))) => Line::from(message.as_str().red()),
+            (None, None) => Line::from(vec![
+                format!("0-9 set {} ", self.get_score_name()).yellow(),
+                Span::raw(
+                    "x unset  s/S score  t tag  e title  d description  / filter  r reload  q quit",
+                )
+                .dark_gray(),
+            ]),
+        };
+
+        frame.render_widget(line, area);
+    }
+}
+
+/// Image drawn with Unicode half blocks, two pixels per cell, centered horizontally
+struct HalfBlocks<'a>(&'a RgbaImage);
+
+impl Widget for HalfBlocks<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let image = self.0;
+        let width = image.width().min(u32::from(area.width));
+        let rows = image.height().div_ceil(2).min(u32::from(area.height));
+        let left = area.x + (area.width - width as u16) / 2;
+
+        let get_color = |x: u32, y: u32| match image.get_pixel_checked(x, y) {
+            Some(pixel) if pixel.0[3] >= 128 => {
+                let [r, g, b, _] = pixel.0;
+                Some(style::Color::Rgb(r, g, b))
+            }
+            _ => None,
+        };
+
+        for row in 0..rows {
+            for x in 0..width {
+                let cell = &mut buf[(left + x as u16, area.y + row as u16)];
+                match (get_color(x, row * 2), get_color(x, row * 2 + 1)) {
+                    (Some(upper), lower) => cell
+                        .set_char('▀')
+                        .set_fg(upper)
+                        .set_bg(lower.unwrap_or(style::Color::Reset)),
+                    (None, Some(lower)) => cell.set_char('▄').set_fg(lower),
+                    (None, None) => cell.set_char(' '),
+                };
+            }
+        }
+    }
+}
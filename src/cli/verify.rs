@@ -0,0 +1,239 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, info, warn};
+use serde_json::json;
+use std::{fs, path::PathBuf};
+
+use super::{list, FilterArgs};
+use crate::{
+    models::{ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Number of verified images after which progress is saved to the metadata file
+const SAVE_INTERVAL: usize = 100;
+
+pub fn verify_images(
+    roots: &[ImageRoot],
+    filters: &FilterArgs,
+    older_than: Option<Duration>,
+    budget: Option<u64>,
+    use_json_format: bool,
+) -> Result<()> {
+    info!("verifying image hashes...");
+    let metas = utils::common::load_root_metas(roots)?;
+    let mut candidates = list::filter_metas(roots, metas, filters)?;
+
+    let now = Utc::now();
+    if let Some(older_than) = older_than {
+        let threshold = now - older_than;
+        info!("only verifying images not verified since: {threshold}");
+        candidates.retain(|meta| meta.last_verified.is_none_or(|date| date < threshold));
+    }
+
+    // Least recently verified first, so that budgeted runs eventually cover everything
+    candidates.sort_by_key(|meta| meta.last_verified);
+    debug!("{} images to verify", candidates.len());
+
+    let mut hashed_bytes: u64 = 0;
+    let mut processed = 0;
+    let mut pending: Vec<(String, PathBuf)> = vec![];
+    let mut verified_count = 0;
+    let mut corrupted = vec![];
+    let mut modified = vec![];
+    let mut changed = vec![];
+    let mut unreadable = vec![];
+    let mut missing = vec![];
+
+    for meta in candidates.iter() {
+        if !meta.path.exists() {
+            warn!("cannot find image: {}", meta.path.display());
+            missing.push(meta);
+            processed += 1;
+            continue;
+        }
+
+        let file_meta = match fs::metadata(&meta.path) {
+            Ok(file_meta) => file_meta,
+            Err(e) => {
+                warn!("cannot read image: {}: {e}", meta.path.display());
+                unreadable.push((meta, e.to_string()));
+                processed += 1;
+                continue;
+            }
+        };
+
+        if let Some(budget) = budget {
+            if hashed_bytes + file_meta.len() > budget {
+                info!("budget of {budget} bytes reached, stopping");
+                break;
+            }
+        }
+
+        // A file that cannot be read through is as damaged as one with a wrong hash
+        let hash = match utils::common::compute_blake3_hash(&meta.path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!("cannot read image: {}: {e}", meta.path.display());
+                unreadable.push((meta, e.to_string()));
+                processed += 1;
+                continue;
+            }
+        };
+        hashed_bytes += file_meta.len();
+        processed += 1;
+
+        if hash == meta.id {
+            debug!("verified: {}", meta.path.display());
+            pending.push((meta.id.clone(), meta.path.clone()));
+            verified_count += 1;
+        } else {
+            match was_modified_since(&file_meta, meta.last_verified) {
+                Some(true) => {
+                    warn!("image has been modified: {}", meta.path.display());
+                    modified.push((meta, hash));
+                }
+                Some(false) => {
+                    warn!("image seems corrupted: {}", meta.path.display());
+                    corrupted.push((meta, hash));
+                }
+                None => {
+                    warn!(
+                        "image has changed since it was indexed: {}",
+                        meta.path.display()
+                    );
+                    changed.push((meta, hash));
+                }
+            }
+        }
+
+        if pending.len() >= SAVE_INTERVAL {
+            save_verified(roots, &pending, now)?;
+            pending.clear();
+        }
+    }
+
+    save_verified(roots, &pending, now)?;
+    let skipped = candidates.len() - processed;
+
+    match use_json_format {
+        true => {
+            let to_json = |(meta, hash): &(&ImageMeta, String)| {
+                json!({
+                    "metadata": meta,
+                    "actual_id": hash
+                })
+            };
+
+            let summary = json!({
+                "verified": verified_count,
+                "hashed_bytes": hashed_bytes,
+                "skipped": skipped,
+                "corrupted": corrupted.iter().map(to_json).collect::<Vec<_>>(),
+                "modified": modified.iter().map(to_json).collect::<Vec<_>>(),
+                "changed": changed.iter().map(to_json).collect::<Vec<_>>(),
+                "unreadable": unreadable
+                    .iter()
+                    .map(|(meta, error)| json!({ "metadata": meta, "error": error }))
+                    .collect::<Vec<_>>(),
+                "missing": missing,
+            });
+            let summary_json = serde_json::to_string(&summary)?;
+            println!("{summary_json}");
+        }
+        false => {
+            if !corrupted.is_empty() {
+                println!("{} corrupted:", corrupted.len());
+                for (meta, _) in corrupted.iter() {
+                    println!("- {}", meta.path.display());
+                }
+                println!();
+            }
+
+            if !modified.is_empty() {
+                println!("{} modified:", modified.len());
+                for (meta, _) in modified.iter() {
+                    println!("- {}", meta.path.display());
+                }
+                println!();
+            }
+
+            if !changed.is_empty() {
+                println!("{} changed, never verified:", changed.len());
+                for (meta, _) in changed.iter() {
+                    println!("- {}", meta.path.display());
+                }
+                println!();
+            }
+
+            if !unreadable.is_empty() {
+                println!("{} unreadable:", unreadable.len());
+                for (meta, error) in unreadable.iter() {
+                    println!("- {}: {}", meta.path.display(), error);
+                }
+                println!();
+            }
+
+            if !missing.is_empty() {
+                println!("{} missing:", missing.len());
+                for meta in missing.iter() {
+                    println!("- {}", meta.path.display());
+                }
+                println!();
+            }
+
+            println!(
+                "{} verified ({} bytes hashed), {} skipped",
+                verified_count, hashed_bytes, skipped
+            );
+        }
+    }
+
+    let failures =
+        corrupted.len() + modified.len() + changed.len() + unreadable.len() + missing.len();
+    if failures > 0 {
+        bail!("verification failed for {} images", failures);
+    }
+
+    Ok(())
+}
+
+/// Whether the file has been written to since its last verification, unknown when it was never verified
+fn was_modified_since(
+    file_meta: &fs::Metadata,
+    last_verified: Option<DateTime<Utc>>,
+) -> Option<bool> {
+    let last_verified = last_verified?;
+    match file_meta.modified() {
+        Ok(modified) => Some(DateTime::<Utc>::from(modified) > last_verified),
+        Err(_) => None,
+    }
+}
+
+fn save_verified(
+    roots: &[ImageRoot],
+    verified: &[(String, PathBuf)],
+    date: DateTime<Utc>,
+) -> Result<()> {
+    if verified.is_empty() {
+        return Ok(());
+    }
+
+    debug!("saving verification date for {} images", verified.len());
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            for meta in metas.iter_mut() {
+                if verified
+                    .iter()
+                    .any(|(id, path)| *id == meta.id && *path == meta.path)
+                {
+                    meta.last_verified = Some(date);
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use log::{debug, error, info, warn};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time,
+};
+
+use super::scan;
+use crate::{
+    models::{Configuration, ImageMeta, ImageRoot},
+    utils,
+};
+
+/// Paths touched by filesystem events received in a short period of time
+#[derive(Debug, Default)]
+struct EventBatch {
+    touched: HashSet<PathBuf>,
+    /// Old and new paths of renamed files and directories
+    renames: Vec<(PathBuf, PathBuf)>,
+    /// Whether events were dropped, for example when the kernel event queue overflowed
+    needs_resync: bool,
+}
+
+impl EventBatch {
+    fn add(&mut self, event: Event) {
+        if event.need_rescan() {
+            self.needs_resync = true;
+        }
+
+        match event.kind {
+            EventKind::Access(_) => {}
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                self.renames
+                    .push((event.paths[0].clone(), event.paths[1].clone()));
+                self.touched.extend(event.paths);
+            }
+            _ => self.touched.extend(event.paths),
+        }
+    }
+
+    fn is_touched(&self, path: &Path) -> bool {
+        self.touched.iter().any(|touched| path.starts_with(touched))
+    }
+
+    /// New path of a file after the renames of the batch, including renames of its parents
+    fn get_renamed_path(&self, path: &Path) -> Option<PathBuf> {
+        let mut renamed_path = path.to_path_buf();
+        for (from, to) in self.renames.iter() {
+            if let Ok(relative_path) = renamed_path.strip_prefix(from) {
+                renamed_path = to.join(relative_path);
+            }
+        }
+
+        (renamed_path != path).then_some(renamed_path)
+    }
+}
+
+/// Changes to apply to the metadata files
+#[derive(Debug, Default)]
+struct MetadataChanges {
+    moved: Vec<(ImageMeta, PathBuf)>,
+    deleted: Vec<ImageMeta>,
+    added: Vec<ImageMeta>,
+}
+
+impl MetadataChanges {
+    fn is_empty(&self) -> bool {
+        self.moved.is_empty() && self.deleted.is_empty() && self.added.is_empty()
+    }
+}
+
+pub fn watch_images(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    debounce: time::Duration,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for root in roots.iter() {
+        watcher.watch(&root.path, RecursiveMode::Recursive)?;
+        info!("watching: {}", root.path.display());
+    }
+
+    let metadata_paths = utils::common::get_metadata_paths(roots);
+    loop {
+        let mut batch = EventBatch::default();
+        match receiver.recv() {
+            Ok(Ok(event)) => batch.add(event),
+            Ok(Err(e)) => {
+                warn!("filesystem watcher error: {e}");
+                continue;
+            }
+            Err(_) => bail!("filesystem watcher stopped"),
+        }
+
+        // Wait for events to settle, so that files are complete and renames are paired
+        loop {
+            match receiver.recv_timeout(debounce) {
+                Ok(Ok(event)) => batch.add(event),
+                Ok(Err(e)) => warn!("filesystem watcher error: {e}"),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => bail!("filesystem watcher stopped"),
+            }
+        }
+
+        // Some events were lost, so any image of the roots might have changed
+        if batch.needs_resync {
+            warn!("missed filesystem events, syncing all roots");
+            batch
+                .touched
+                .extend(roots.iter().map(|root| root.path.clone()));
+        }
+
+        // Writing metadata files triggers events too
+        batch.touched.retain(|path| {
+            let path = path.to_string_lossy();
+            !metadata_paths
+                .iter()
+                .any(|metadata_path| path.starts_with(&*metadata_path.to_string_lossy()))
+        });
+
+        if batch.touched.is_empty() {
+            continue;
+        }
+
+        debug!("processing events for {} paths", batch.touched.len());
+        if let Err(e) = sync_metadatas(configuration, roots, &batch) {
+            error!("failed to update metadata: {e}");
+        }
+    }
+}
+
+fn sync_metadatas(
+    configuration: &Configuration,
+    roots: &[ImageRoot],
+    batch: &EventBatch,
+) -> Result<()> {
+    let metas = utils::common::load_root_metas(roots)?;
+    let known_paths: HashSet<&Path> = metas.iter().map(|meta| meta.path.as_path()).collect();
+
+    let mut new_images = vec![];
+    for root in roots.iter() {
+        let is_root_touched = batch.is_touched(&root.path)
+            || batch
+                .touched
+                .iter()
+                .any(|path| path.starts_with(&root.path));
+
+        if !is_root_touched {
+            continue;
+        }
+
+        for image_path in utils::common::get_root_images(root, &configuration.walker)? {
+            if batch.is_touched(&image_path) && !known_paths.contains(image_path.as_path()) {
+                new_images.push(image_path);
+            }
+        }
+    }
+
+    let mut missing_metas: Vec<&ImageMeta> = metas
+        .iter()
+        .filter(|meta| batch.is_touched(&meta.path) && !meta.path.exists())
+        .collect();
+
+    let mut changes = MetadataChanges::default();
+
+    // Follow renames by path first
+    missing_metas.retain(|meta| {
+        let Some(renamed_path) = batch.get_renamed_path(&meta.path) else {
+            return true;
+        };
+
+        match new_images.iter().position(|image| *image == renamed_path) {
+            Some(index) => {
+                changes
+                    .moved
+                    .push(((*meta).clone(), new_images.remove(index)));
+                false
+            }
+            None => true,
+        }
+    });
+
+    // Then fall back to the id, for moves that were not reported as renames
+    let mut new_images_by_hash: HashMap<String, Vec<&Path>> = HashMap::new();
+    for image_path in new_images.iter() {
+        match utils::common::compute_blake3_hash(image_path) {
+            Ok(hash) => new_images_by_hash.entry(hash).or_default().push(image_path),
+            Err(e) => warn!("failed to hash {}: {}", image_path.display(), e),
+        }
+    }
+
+    let mut missing_by_id: HashMap<&str, Vec<&ImageMeta>> = HashMap::new();
+    for meta in missing_metas.into_iter() {
+        missing_by_id.entry(&meta.id).or_default().push(meta);
+    }
+
+    for (id, metas) in missing_by_id.into_iter() {
+        let candidates = new_images_by_hash.remove(id).unwrap_or_default();
+        let (moved, deleted, unmatched) = scan::pair_moved_images(metas, candidates);
+
+        for (image_path, meta) in moved.into_iter() {
+            changes.moved.push((meta.clone(), image_path.to_path_buf()));
+        }
+        changes.deleted.extend(deleted.into_iter().cloned());
+        if !unmatched.is_empty() {
+            new_images_by_hash.insert(id.to_owned(), unmatched);
+        }
+    }
+
+    let mut remaining_images: Vec<&Path> = new_images_by_hash.into_values().flatten().collect();
+    remaining_images.sort();
+    for image_path in remaining_images.into_iter() {
+        match ImageMeta::create_from_image(image_path, &configuration.theme) {
+            Ok(meta) => changes.added.push(meta),
+            Err(e) => warn!(
+                "failed to generate metadata of {}: {}",
+                image_path.display(),
+                e
+            ),
+        }
+    }
+
+    if changes.is_empty() {
+        debug!("no metadata to update");
+        return Ok(());
+    }
+
+    save_changes(roots, &changes)?;
+    log_changes(&changes);
+    Ok(())
+}
+
+fn save_changes(roots: &[ImageRoot], changes: &MetadataChanges) -> Result<()> {
+    let get_target_file = |path: &Path| {
+        roots
+            .iter()
+            .find(|root| root.contains(path))
+            .map(|root| root.metadata_path.as_path())
+    };
+    let get_source_file = |meta: &ImageMeta| {
+        roots
+            .iter()
+            .find(|root| meta.root.as_ref() == Some(&root.name))
+            .map(|root| root.metadata_path.as_path())
+    };
+    let is_same = |a: &ImageMeta, b: &ImageMeta| a.id == b.id && a.path == b.path;
+
+    for metadata_path in utils::common::get_metadata_paths(roots) {
+        utils::common::update_image_metas(metadata_path, |metas| {
+            metas.retain_mut(|meta| {
+                if changes.deleted.iter().any(|deleted| is_same(deleted, meta)) {
+                    return false;
+                }
+
+                match changes.moved.iter().find(|(old, _)| is_same(old, meta)) {
+                    Some((_, new_path)) if get_target_file(new_path) == Some(metadata_path) => {
+                        meta.path = new_path.clone();
+                        true
+                    }
+                    // Moved to a root that uses another metadata file
+                    Some(_) => false,
+                    None => true,
+                }
+            });
+
+            for (old, new_path) in changes.moved.iter() {
+                let is_moved_in = get_target_file(new_path) == Some(metadata_path)
+                    && get_source_file(old) != Some(metadata_path);
+
+                if is_moved_in {
+                    let mut meta = old.clone();
+                    meta.path = new_path.clone();
+                    metas.push(meta);
+                }
+            }
+
+            for meta in changes.added.iter() {
+                if get_target_file(&meta.path) == Some(metadata_path) {
+                    metas.push(meta.clone());
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+fn log_changes(changes: &MetadataChanges) {
+    let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+
+    for (old, new_path) in changes.moved.iter() {
+        println!(
+            "{now} moved: {} -> {}",
+            old.path.display(),
+            new_path.display()
+        );
+    }
+
+    for meta in changes.deleted.iter() {
+        println!("{now} deleted: {}", meta.path.display());
+    }
+
+    for meta in changes.added.iter() {
+        println!("{now} added: {}", meta.path.display());
+    }
+}
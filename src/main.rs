@@ -1,21 +1,28 @@
-use anyhow::{ensure, Result};
-use clap::Parser;
-use cli::{Cli, Commands};
+use anyhow::{ensure, Context, Result};
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use cli::{Cli, Commands, ConfigurationCommands};
 use log::{error, info, warn};
-use std::process::ExitCode;
+use models::{ConfigurationOverrides, ValueSource};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+use utils::common::{CONFIG_VAR, META_PATH_VAR, ROOT_VAR};
 
 mod cli;
 mod models;
 mod utils;
 
 fn main() -> ExitCode {
-    let args = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     env_logger::Builder::new()
         .filter_level(args.verbose.log_level_filter())
         .init();
 
     info!("process cli args");
-    match process_args(args) {
+    match process_args(args, &matches) {
         Ok(_) => ExitCode::SUCCESS,
         Err(e) => {
             error!("{}", e);
@@ -24,71 +31,294 @@ fn main() -> ExitCode {
     }
 }
 
-fn process_args(args: Cli) -> Result<()> {
+fn process_args(args: Cli, matches: &ArgMatches) -> Result<()> {
     info!("getting config file");
-    let config_file = utils::common::get_config_file()?;
-    if !config_file.exists() {
-        utils::common::create_config_file()?;
-        info!("config file created");
-    }
+    let is_default_location = args.config_file.is_none() && env::var(CONFIG_VAR).is_err();
+    let config_file = match args.config_file {
+        Some(config_file) => utils::common::expand_path(&config_file)?,
+        None => utils::common::get_config_file()?,
+    };
 
-    info!("loading config");
-    let config = utils::common::load_config(config_file)?;
+    let overrides = get_overrides(matches);
 
-    ensure!(
-        config.root_images_dir.exists(),
-        "could not find root images directory: {}",
-        config.root_images_dir.display()
-    );
-    ensure!(
-        config.metadata_path.exists(),
-        "could not find metadata file: {}",
-        config.metadata_path.display()
+    let is_generate = matches!(
+        args.command,
+        Commands::Configuration {
+            command: ConfigurationCommands::Generate { .. }
+        }
     );
+    if !is_generate {
+        ensure_config_file(&config_file, is_default_location)?;
+    }
+
+    if let Commands::Configuration { command } = args.command {
+        return cli::handle_config_command(command, &config_file, &overrides);
+    }
+
+    info!("loading config");
+    let config = utils::common::load_config(&config_file, &overrides)?;
 
     info!("metadata_path: {:?}", config.metadata_path);
     match args.command {
         Commands::List {
-            active_directories,
-            scores,
-            width_range,
-            height_range,
-            tags,
-            ignore_config,
+            filters,
             use_json_format,
         } => {
-            let mut active_directories = active_directories;
-            let mut score_filters = scores;
-            let mut width_range = width_range;
-            let mut height_range = height_range;
-
-            if !ignore_config {
-                active_directories = active_directories.or(config.filters.active_directories);
-                score_filters = score_filters.or(config.filters.scores);
-                width_range = width_range.or(config.filters.width_range);
-                height_range = height_range.or(config.filters.height_range);
-            } else {
-                info!("ignore_config flag has been added");
-            }
+            let filters = cli::resolve_filters(filters, &config)?;
+            let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
 
             warn!("right now, metadata file is required to list images");
-            cli::list_images_using_metadata(
-                &config.root_images_dir,
-                &config.metadata_path,
-                active_directories,
-                score_filters,
-                width_range,
-                height_range,
-                tags,
+            cli::list_images_using_metadata(&roots, &filters, use_json_format)
+        }
+        cli::Commands::Scan {
+            root_name,
+            deep,
+            use_json_format,
+        } => {
+            let roots = utils::common::get_image_roots(&config, root_name.as_deref())?;
+            cli::scan_images(&roots, &config.walker, deep, use_json_format)
+        }
+        cli::Commands::Verify {
+            filters,
+            older_than,
+            budget,
+            use_json_format,
+        } => {
+            let filters = cli::resolve_filters(filters, &config)?;
+            let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
+            cli::verify_images(&roots, &filters, older_than, budget, use_json_format)
+        }
+        cli::Commands::Dupes {
+            root_name,
+            perceptual,
+            threshold,
+            keep,
+            action,
+            dry_run,
+            use_json_format,
+        } => {
+            let roots = utils::common::get_image_roots(&config, root_name.as_deref())?;
+            let threshold = perceptual.then_some(threshold);
+            cli::handle_dupes(
+                &roots,
+                &config,
+                threshold,
+                keep,
+                action,
+                dry_run,
                 use_json_format,
             )
         }
-        cli::Commands::Scan { use_json_format } => cli::scan_images(
-            &config.root_images_dir,
-            &config.metadata_path,
+        cli::Commands::Similar {
+            identifier,
+            threshold,
+            root_name,
             use_json_format,
-        ),
-        cli::Commands::Configuration { command } => cli::handle_config_command(command, &config),
+        } => {
+            let roots = utils::common::get_image_roots(&config, root_name.as_deref())?;
+            cli::find_similar_images(
+                &roots,
+                &config.walker,
+                &identifier,
+                threshold,
+                use_json_format,
+            )
+        }
+        cli::Commands::Export { command } => match command {
+            cli::ExportCommands::Html {
+                filters,
+                out_dir,
+                thumbnail_size,
+            } => {
+                let filters = cli::resolve_filters(filters, &config)?;
+                let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
+                let out_dir = utils::common::expand_path(&out_dir)?;
+                cli::export_html(&roots, &filters, &out_dir, thumbnail_size)
+            }
+        },
+        cli::Commands::Thumb {
+            identifier,
+            size,
+            all,
+            filters,
+        } => {
+            let filters = cli::resolve_filters(filters, &config)?;
+            let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
+            match (all, identifier) {
+                (false, Some(identifier)) => cli::print_thumbnail(&roots, &identifier, size),
+                _ => cli::generate_thumbnails(&roots, &filters, size),
+            }
+        }
+        cli::Commands::Preview {
+            identifier,
+            size,
+            protocol,
+            root_name,
+        } => {
+            let roots = utils::common::get_image_roots(&config, root_name.as_deref())?;
+            cli::preview_image(&roots, &identifier, size, protocol)
+        }
+        cli::Commands::Tui { filters } => cli::run_tui(&config, filters),
+        cli::Commands::Rate {
+            score,
+            order,
+            protocol,
+            filters,
+        } => {
+            let filters = cli::resolve_filters(filters, &config)?;
+            let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
+            cli::rate_images(&config, &roots, &filters, &score, order, protocol)
+        }
+        cli::Commands::Duel {
+            score,
+            protocol,
+            filters,
+        } => {
+            let filters = cli::resolve_filters(filters, &config)?;
+            let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
+            cli::run_duels(&config, &roots, &filters, &score, protocol)
+        }
+        #[cfg(unix)]
+        cli::Commands::Daemon { filters, interval } => {
+            let roots = utils::common::get_image_roots(&config, filters.root_name.as_deref())?;
+            cli::run_daemon(&config, &roots, &filters, interval)
+        }
+        #[cfg(unix)]
+        cli::Commands::Ctl { command } => cli::handle_ctl_command(command, &config),
+        #[cfg(not(unix))]
+        cli::Commands::Daemon { .. } | cli::Commands::Ctl { .. } => {
+            anyhow::bail!(
+                "the daemon is only supported on unix, it is controlled through a unix socket"
+            )
+        }
+        cli::Commands::Serve { bind, token } => cli::serve_api(&config, bind, token),
+        cli::Commands::Watch {
+            root_name,
+            debounce,
+        } => {
+            let roots = utils::common::get_image_roots(&config, root_name.as_deref())?;
+            let debounce = debounce.to_std().context("debounce should be positive")?;
+            cli::watch_images(&config, &roots, debounce)
+        }
+        cli::Commands::Configuration { .. } => unreachable!("handled before loading config"),
         cli::Commands::Metadata { command } => cli::handle_metadata_command(command, &config),
     }
 }
+
+/// Create the config file when it is missing from its default location
+fn ensure_config_file(config_file: &Path, is_default_location: bool) -> Result<()> {
+    if config_file.exists() {
+        return Ok(());
+    }
+
+    // A path given explicitly is most likely a typo, better not to leave an empty config there
+    ensure!(
+        is_default_location,
+        "config file does not exist: {}, create it with `kanumi config generate`",
+        config_file.display()
+    );
+    utils::common::create_config_file(config_file)?;
+    info!("config file created");
+    Ok(())
+}
+
+fn get_overrides(matches: &ArgMatches) -> ConfigurationOverrides {
+    ConfigurationOverrides {
+        root_images_dir: get_override(matches, "root_images_dir", "--root", ROOT_VAR),
+        metadata_path: get_override(matches, "metadata_path", "--meta", META_PATH_VAR),
+    }
+}
+
+/// Get an overriding path given on the command line or in the environment, with where it comes from
+fn get_override(
+    matches: &ArgMatches,
+    id: &str,
+    cli_arg: &'static str,
+    env_var: &'static str,
+) -> Option<(PathBuf, ValueSource)> {
+    let value = matches.get_one::<PathBuf>(id)?.clone();
+    let source = match matches.value_source(id) {
+        Some(clap::parser::ValueSource::EnvVariable) => ValueSource::Environment(env_var),
+        _ => ValueSource::CommandLine(cli_arg),
+    };
+
+    Some((value, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    const CONFIG: &str = r#"
+        root_path = "/tmp/from-config"
+        meta_path = "/tmp/from-config/metadatas.json"
+
+        [filters]
+    "#;
+
+    fn load_with_args(config_file: &Path, args: &[&str]) -> Result<models::Configuration> {
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        utils::common::load_config(config_file, &get_overrides(&matches))
+    }
+
+    #[test]
+    fn overrides_config_with_env_then_flags() {
+        let config_file =
+            env::temp_dir().join(format!("kanumi-test-{}-overrides.toml", process::id()));
+        fs::write(&config_file, CONFIG).unwrap();
+
+        let config = load_with_args(&config_file, &["kanumi", "list"]).unwrap();
+        assert_eq!(config.root_images_dir, PathBuf::from("/tmp/from-config"));
+        assert_eq!(config.sources["root_path"], ValueSource::ConfigFile);
+
+        // Only this test reads these variables, so setting them does not race with other tests
+        env::set_var(ROOT_VAR, "/tmp/from-env");
+        env::set_var(META_PATH_VAR, "$KANUMI_ROOT/metadatas.json");
+        let env_config = load_with_args(&config_file, &["kanumi", "list"]);
+        let flag_config = load_with_args(
+            &config_file,
+            &["kanumi", "--root", "/tmp/from-flag", "list"],
+        );
+        env::remove_var(ROOT_VAR);
+        env::remove_var(META_PATH_VAR);
+        fs::remove_file(&config_file).unwrap();
+
+        let env_config = env_config.unwrap();
+        assert_eq!(env_config.root_images_dir, PathBuf::from("/tmp/from-env"));
+        assert_eq!(
+            env_config.metadata_path,
+            PathBuf::from("/tmp/from-env/metadatas.json")
+        );
+        assert_eq!(
+            env_config.sources["root_path"],
+            ValueSource::Environment(ROOT_VAR)
+        );
+
+        let flag_config = flag_config.unwrap();
+        assert_eq!(flag_config.root_images_dir, PathBuf::from("/tmp/from-flag"));
+        assert_eq!(
+            flag_config.sources["root_path"],
+            ValueSource::CommandLine("--root")
+        );
+        assert_eq!(
+            flag_config.sources["meta_path"],
+            ValueSource::Environment(META_PATH_VAR)
+        );
+    }
+
+    #[test]
+    fn refuses_missing_explicit_config_file() {
+        let config_dir =
+            env::temp_dir().join(format!("kanumi-test-{}-missing-config", process::id()));
+        let config_file = config_dir.join("config.toml");
+
+        assert!(ensure_config_file(&config_file, false).is_err());
+        assert!(!config_file.exists());
+
+        ensure_config_file(&config_file, true).unwrap();
+        let created = fs::read_to_string(&config_file);
+        fs::remove_dir_all(&config_dir).unwrap();
+        assert!(toml::from_str::<models::Configuration>(&created.unwrap()).is_ok());
+    }
+}
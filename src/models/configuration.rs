@@ -1,10 +1,22 @@
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
+use chrono::{Duration, NaiveDateTime};
 use directories::UserDirs;
-use log::{debug, info};
+use image::ImageFormat;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use std::{ops::RangeInclusive, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    ops::RangeInclusive,
+    path::{self, Component, Path, PathBuf},
+};
 
-use super::ScoreFilter;
+use super::{
+    image_root::{ConfigurationRoot, DEFAULT_ROOT_NAME},
+    schedule::ScheduleEntry,
+    Color, ColorTheme, ImageRoot, ScoreFilter,
+};
+use crate::utils;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Configuration {
@@ -14,11 +26,310 @@ pub struct Configuration {
     #[serde(rename = "meta_path")]
     pub metadata_path: PathBuf,
 
+    /// Range of values that image scores are allowed to take
+    #[serde(rename = "score_range", default)]
+    pub score_range: Option<RangeInclusive<u8>>,
+
+    /// Definitions of the scores images can be given. When any is defined, other names are rejected
+    #[serde(rename = "scores", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scores: BTreeMap<String, ConfigurationScore>,
+
+    /// Name of the profile used when none is given on the command line
+    #[serde(rename = "default_profile", default)]
+    pub default_profile: Option<String>,
+
     #[serde(rename = "filters")]
     pub filters: ConfigurationFilters,
+
+    /// Named filter presets that can be selected with `--profile`
+    #[serde(
+        rename = "profiles",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profiles: BTreeMap<String, ConfigurationFilters>,
+
+    /// How directories of images are walked through
+    #[serde(rename = "walker", default)]
+    pub walker: ConfigurationWalker,
+
+    /// How the light or dark theme of images is detected
+    #[serde(rename = "theme", default)]
+    pub theme: ConfigurationTheme,
+
+    /// Additional directories of images, each with its own rules
+    #[serde(rename = "roots", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roots: BTreeMap<String, ConfigurationRoot>,
+
+    /// How the wallpaper daemon rotates images
+    #[serde(rename = "daemon", default)]
+    pub daemon: ConfigurationDaemon,
+
+    /// Profiles selected depending on the date and time. The first active entry wins
+    #[serde(rename = "schedule", default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleEntry>,
+
+    /// Where each top-level value comes from
+    #[serde(skip)]
+    pub sources: BTreeMap<String, ValueSource>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConfigurationWalker {
+    /// Gitignore-style globs of files and directories to skip in every root
+    #[serde(rename = "exclude")]
+    pub exclude: Vec<String>,
+
+    /// Whether hidden files and directories are walked through. Default is: true
+    #[serde(rename = "include_hidden")]
+    pub include_hidden: bool,
+
+    /// Whether symbolic links to directories are followed. Default is: false
+    #[serde(rename = "follow_symlinks")]
+    pub follow_symlinks: bool,
+
+    /// Image formats that are accepted, by extension name (e.g. `png`, `jpeg`, `avif`)
+    #[serde(rename = "formats")]
+    pub formats: Vec<String>,
+}
+
+impl Default for ConfigurationWalker {
+    fn default() -> Self {
+        let mut formats = vec!["bmp", "gif", "jpeg", "png", "qoi", "tiff", "webp"];
+
+        // AVIF images cannot be decoded without dav1d
+        if cfg!(feature = "avif-decoder") {
+            formats.insert(0, "avif");
+        }
+
+        ConfigurationWalker {
+            exclude: vec![],
+            include_hidden: true,
+            follow_symlinks: false,
+            formats: formats.iter().map(|format| format.to_string()).collect(),
+        }
+    }
+}
+
+impl ConfigurationWalker {
+    /// Get the accepted image formats, leaving out the ones this build cannot decode
+    pub fn image_formats(&self) -> Result<Vec<ImageFormat>> {
+        let mut image_formats = vec![];
+        for format in self.formats.iter() {
+            match ImageFormat::from_extension(format) {
+                Some(ImageFormat::Avif) if !cfg!(feature = "avif-decoder") => {}
+                Some(image_format) => image_formats.push(image_format),
+                None => bail!("unknown image format: `{format}`"),
+            }
+        }
+
+        Ok(image_formats)
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.image_formats()?;
+
+        let has_avif = self.formats.iter().any(|format| format == "avif");
+        if has_avif && !cfg!(feature = "avif-decoder") {
+            warn!("avif images are skipped, kanumi was built without the `avif-decoder` feature");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConfigurationTheme {
+    /// Mean lightness (0-1) under which an image is dark. Default is: 0.5
+    #[serde(rename = "threshold")]
+    pub threshold: f64,
+
+    /// Images classified with a lower confidence (0-1) are left without theme. Default is: 0.1
+    #[serde(rename = "min_confidence")]
+    pub min_confidence: f64,
+}
+
+impl Default for ConfigurationTheme {
+    fn default() -> Self {
+        ConfigurationTheme {
+            threshold: 0.5,
+            min_confidence: 0.1,
+        }
+    }
+}
+
+impl ConfigurationTheme {
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.threshold > 0.0 && self.threshold < 1.0,
+            "theme.threshold should be between 0 and 1, got: {}",
+            self.threshold
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.min_confidence),
+            "theme.min_confidence should be between 0 and 1, got: {}",
+            self.min_confidence
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConfigurationDaemon {
+    /// Command that applies a wallpaper, where `{path}` is replaced by the image path
+    /// (e.g. `["swww", "img", "{path}"]`)
+    #[serde(rename = "setter")]
+    pub setter: Vec<String>,
+
+    /// Time between two wallpapers (e.g. 30m, 2h). Default is: 30m
+    #[serde(rename = "interval")]
+    pub interval: String,
+
+    /// Name of the score set by `kanumi ctl rate`. Default is: rating
+    #[serde(rename = "score")]
+    pub score: String,
+
+    /// Path of the control socket. Default is `daemon.sock` in the runtime directory
+    #[serde(rename = "socket_path", skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+}
+
+impl Default for ConfigurationDaemon {
+    fn default() -> Self {
+        ConfigurationDaemon {
+            setter: vec![],
+            interval: String::from("30m"),
+            score: String::from("rating"),
+            socket_path: None,
+        }
+    }
+}
+
+impl ConfigurationDaemon {
+    fn validate(&self) -> Result<()> {
+        if let Err(e) = self.get_interval() {
+            bail!("invalid `daemon.interval`: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Get the time between two wallpapers
+    pub fn get_interval(&self) -> Result<Duration> {
+        let interval = utils::common::parse_duration(&self.interval)?;
+        ensure!(
+            interval > Duration::zero(),
+            "interval should be positive, got: {}",
+            self.interval
+        );
+
+        Ok(interval)
+    }
+}
+
+/// Definition of a score: the values it can take and what they mean
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConfigurationScore {
+    /// What the score is about
+    #[serde(
+        rename = "description",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub description: String,
+
+    /// Lowest value. Default is the start of `score_range`
+    #[serde(rename = "min", default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<u8>,
+
+    /// Highest value. Default is the end of `score_range`
+    #[serde(rename = "max", default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<u8>,
+
+    /// Value assumed for images that do not have this score, when filtering
+    #[serde(rename = "default", default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<u8>,
+
+    /// Meaning of values (e.g. `1 = "meh"`, `5 = "love"`)
+    #[serde(rename = "labels", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ConfigurationScore {
+    /// Get the values the score can take, completed with `score_range`
+    pub fn get_range(&self, score_range: Option<&RangeInclusive<u8>>) -> RangeInclusive<u8> {
+        let start = self.min.or(score_range.map(|range| *range.start()));
+        let end = self.max.or(score_range.map(|range| *range.end()));
+        start.unwrap_or(u8::MIN)..=end.unwrap_or(u8::MAX)
+    }
+
+    pub fn get_label(&self, value: u8) -> Option<&str> {
+        self.labels.get(&value.to_string()).map(String::as_str)
+    }
+
+    fn validate(&self, name: &str, score_range: Option<&RangeInclusive<u8>>) -> Result<()> {
+        let range = self.get_range(score_range);
+        ensure_range_is_valid(&format!("scores.{name}"), &range)?;
+
+        if let Some(default) = self.default {
+            ensure!(
+                range.contains(&default),
+                "`scores.{}.default` should be between {} and {}, got: {}",
+                name,
+                range.start(),
+                range.end(),
+                default
+            );
+        }
+
+        for key in self.labels.keys() {
+            match key.parse::<u8>() {
+                Ok(value) if range.contains(&value) => {}
+                _ => bail!(
+                    "`scores.{}.labels` should be values between {} and {}, got: {}",
+                    name,
+                    range.start(),
+                    range.end(),
+                    key
+                ),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Values given on the command line or in the environment, that take precedence over the configuration file
+#[derive(Debug, Default)]
+pub struct ConfigurationOverrides {
+    pub root_images_dir: Option<(PathBuf, ValueSource)>,
+    pub metadata_path: Option<(PathBuf, ValueSource)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueSource {
+    Default,
+    ConfigFile,
+    Environment(&'static str),
+    CommandLine(&'static str),
+}
+
+impl fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueSource::Default => write!(f, "default"),
+            ValueSource::ConfigFile => write!(f, "config file"),
+            ValueSource::Environment(var) => write!(f, "env: {var}"),
+            ValueSource::CommandLine(arg) => write!(f, "cli: {arg}"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ConfigurationFilters {
     #[serde(rename = "active_dirs")]
     pub active_directories: Option<Vec<PathBuf>>,
@@ -31,6 +342,31 @@ pub struct ConfigurationFilters {
 
     #[serde(rename = "height")]
     pub height_range: Option<RangeInclusive<usize>>,
+
+    #[serde(rename = "tags")]
+    pub tags: Option<Vec<String>>,
+
+    #[serde(rename = "colors")]
+    pub colors: Option<Vec<Color>>,
+
+    #[serde(rename = "theme")]
+    pub theme: Option<ColorTheme>,
+
+    /// Colors as `#rrggbb`, that images should have one close to in their palette
+    #[serde(rename = "near_colors")]
+    pub near_colors: Option<Vec<String>>,
+
+    /// Maximum perceptual distance (0-100) between close colors
+    #[serde(rename = "distance")]
+    pub distance: Option<f32>,
+
+    /// JSON file of colors to rank images by
+    #[serde(rename = "palette")]
+    pub palette_file: Option<PathBuf>,
+
+    /// Maximum perceptual distance (0-100) between the palette of images and the `palette` colors
+    #[serde(rename = "palette_distance")]
+    pub palette_distance: Option<f32>,
 }
 
 impl Configuration {
@@ -52,18 +388,431 @@ impl Configuration {
             scores: None,
             width_range: Some(RangeInclusive::new(0, 10_000)),
             height_range: Some(RangeInclusive::new(0, 10_000)),
+            tags: None,
+            colors: None,
+            theme: None,
+            near_colors: None,
+            distance: None,
+            palette_file: None,
+            palette_distance: None,
         };
 
         Configuration {
             root_images_dir,
             metadata_path,
+            score_range: Some(RangeInclusive::new(0, 9)),
+            scores: BTreeMap::new(),
+            default_profile: None,
             filters,
+            profiles: BTreeMap::new(),
+            walker: ConfigurationWalker::default(),
+            theme: ConfigurationTheme::default(),
+            daemon: ConfigurationDaemon::default(),
+            roots: BTreeMap::new(),
+            schedule: vec![],
+            sources: BTreeMap::new(),
         }
     }
 
+    /// Check that the configuration values are consistent
+    pub fn validate(&self) -> Result<()> {
+        if let Some(score_range) = &self.score_range {
+            ensure_range_is_valid("score_range", score_range)?;
+        }
+
+        for (name, score) in self.scores.iter() {
+            score.validate(name, self.score_range.as_ref())?;
+        }
+
+        self.filters.validate("filters", self)?;
+        for (name, profile) in self.profiles.iter() {
+            profile.validate(&format!("profiles.{name}"), self)?;
+        }
+
+        ensure!(
+            !self.roots.contains_key(DEFAULT_ROOT_NAME),
+            "root name is reserved for `root_path`: {}",
+            DEFAULT_ROOT_NAME
+        );
+        // Images of nested roots would be walked twice and belong to whichever root comes first
+        let roots = self.image_roots(None)?;
+        let mut root_paths = vec![];
+        for root in roots.iter() {
+            if root.path.as_os_str().is_empty() {
+                match root.name.as_str() {
+                    DEFAULT_ROOT_NAME => bail!("`root_path` should not be empty"),
+                    name => bail!("`roots.{name}.path` should not be empty"),
+                }
+            }
+
+            // Relative paths, symbolic links and `..` would hide that roots overlap
+            root_paths.push(normalize_path(&root.path)?);
+        }
+
+        for (index, root) in roots.iter().enumerate() {
+            for (other_index, other) in roots.iter().enumerate().skip(index + 1) {
+                let (path, other_path) = (&root_paths[index], &root_paths[other_index]);
+                ensure!(
+                    !path.starts_with(other_path) && !other_path.starts_with(path),
+                    "roots `{}` and `{}` should not overlap: {} and {}",
+                    root.name,
+                    other.name,
+                    root.path.display(),
+                    other.path.display()
+                );
+            }
+        }
+
+        self.walker.validate()?;
+        self.theme.validate()?;
+        self.daemon.validate()?;
+        if let Err(e) = self.ensure_score_is_defined(&self.daemon.score) {
+            bail!("invalid `daemon.score`: {e}");
+        }
+
+        if let Some(default_profile) = &self.default_profile {
+            ensure!(
+                self.profiles.contains_key(default_profile),
+                "default profile is not defined: {}",
+                default_profile
+            );
+        }
+
+        for (index, entry) in self.schedule.iter().enumerate() {
+            entry.validate(&format!("schedule[{index}]"))?;
+            ensure!(
+                self.profiles.contains_key(&entry.profile),
+                "scheduled profile is not defined: {}",
+                entry.profile
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Expand `~` and environment variables in configured paths
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.root_images_dir = utils::common::expand_path(&self.root_images_dir)?;
+        self.metadata_path = utils::common::expand_path(&self.metadata_path)?;
+
+        if let Some(socket_path) = &self.daemon.socket_path {
+            self.daemon.socket_path = Some(utils::common::expand_path(socket_path)?);
+        }
+
+        for root in self.roots.values_mut() {
+            root.path = utils::common::expand_path(&root.path)?;
+            if let Some(metadata_path) = &root.metadata_path {
+                root.metadata_path = Some(utils::common::expand_path(metadata_path)?);
+            }
+        }
+
+        let profiles = self.profiles.values_mut();
+        for filters in std::iter::once(&mut self.filters).chain(profiles) {
+            for active_dir in filters.active_directories.iter_mut().flatten() {
+                *active_dir = utils::common::expand_path(active_dir)?;
+            }
+
+            if let Some(palette_file) = &filters.palette_file {
+                filters.palette_file = Some(utils::common::expand_path(palette_file)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get all the image roots, or only the one with the given name
+    pub fn image_roots(&self, name: Option<&str>) -> Result<Vec<ImageRoot>> {
+        let default_root = ConfigurationRoot {
+            path: self.root_images_dir.clone(),
+            metadata_path: None,
+            include: vec![],
+            exclude: vec![],
+        };
+
+        let mut roots = vec![ImageRoot::new(
+            DEFAULT_ROOT_NAME,
+            &default_root,
+            &self.metadata_path,
+        )?];
+
+        for (root_name, root) in self.roots.iter() {
+            roots.push(ImageRoot::new(root_name, root, &self.metadata_path)?);
+        }
+
+        if let Some(name) = name {
+            roots.retain(|root| root.name == name);
+            ensure!(!roots.is_empty(), "no root named: {name}");
+        }
+
+        Ok(roots)
+    }
+
+    /// Get the name of the profile to use: the given one first, then the one set with
+    /// `KANUMI_PROFILE`, then the first active schedule entry, then the default one
+    pub fn resolve_profile_name<'a>(
+        &'a self,
+        name: Option<&'a str>,
+        at: NaiveDateTime,
+    ) -> Result<Option<&'a str>> {
+        if name.is_some() {
+            return Ok(name);
+        }
+
+        if let Some(ValueSource::Environment(_)) = self.sources.get("default_profile") {
+            return Ok(self.default_profile.as_deref());
+        }
+
+        for entry in self.schedule.iter() {
+            if entry.is_active(at)? {
+                info!("scheduled profile is active at {}: {}", at, entry.profile);
+                return Ok(Some(&entry.profile));
+            }
+        }
+
+        Ok(self.default_profile.as_deref())
+    }
+
+    /// Get the filters of a profile, or the default ones when no name is given
+    pub fn get_profile(&self, name: Option<&str>) -> Result<&ConfigurationFilters> {
+        let name = name.or(self.default_profile.as_deref());
+        match name {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile),
+                None => bail!("no profile named: {name}"),
+            },
+            None => Ok(&self.filters),
+        }
+    }
+
+    /// Check that a score is defined, when any score is defined in configuration
+    pub fn ensure_score_is_defined(&self, name: &str) -> Result<()> {
+        if self.scores.is_empty() || self.scores.contains_key(name) {
+            return Ok(());
+        }
+
+        let names = self.scores.keys().map(String::as_str);
+        match utils::common::find_closest_name(name, names) {
+            Some(closest) => bail!("unknown score: `{name}`, did you mean `{closest}`?"),
+            None => bail!(
+                "unknown score: `{}`, expected one of: {}",
+                name,
+                self.scores.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    /// Get the values a score can take: the ones of its definition, or `score_range`
+    pub fn get_score_range(&self, name: &str) -> Option<RangeInclusive<u8>> {
+        match self.scores.get(name) {
+            Some(score) => Some(score.get_range(self.score_range.as_ref())),
+            None => self.score_range.clone(),
+        }
+    }
+
+    /// Get the meaning of a score value, from its definition
+    pub fn get_score_label(&self, name: &str, value: u8) -> Option<&str> {
+        self.scores.get(name)?.get_label(value)
+    }
+
+    /// Check that a score is defined and that the value is allowed for it
+    pub fn validate_score(&self, name: &str, value: u8) -> Result<()> {
+        self.ensure_score_is_defined(name)?;
+
+        if let Some(range) = self.get_score_range(name) {
+            ensure!(
+                range.contains(&value),
+                "score `{}` should be between {} and {}, got: {}",
+                name,
+                range.start(),
+                range.end(),
+                value
+            );
+        }
+
+        Ok(())
+    }
+
     pub fn to_toml_str(&self) -> Result<String> {
         let toml = toml::to_string(&self)?;
         debug!("config serialized to TOML: {}", toml);
         Ok(toml)
     }
 }
+
+impl ConfigurationFilters {
+    fn validate(&self, prefix: &str, configuration: &Configuration) -> Result<()> {
+        if let Some(width_range) = &self.width_range {
+            ensure_range_is_valid(&format!("{prefix}.width"), width_range)?;
+        }
+
+        if let Some(height_range) = &self.height_range {
+            ensure_range_is_valid(&format!("{prefix}.height"), height_range)?;
+        }
+
+        for score_filter in self.scores.iter().flatten() {
+            if let Err(e) = configuration.ensure_score_is_defined(&score_filter.name) {
+                bail!("invalid `{prefix}.scores`: {e}");
+            }
+
+            ensure_range_is_valid(
+                &format!("{prefix}.scores.{}", score_filter.name),
+                &score_filter.range,
+            )?;
+        }
+
+        if let Err(e) = self.get_near_colors() {
+            bail!("invalid `{prefix}.near_colors`: {e}");
+        }
+
+        Ok(())
+    }
+
+    pub fn get_near_colors(&self) -> Result<Option<Vec<[u8; 3]>>> {
+        let Some(near_colors) = &self.near_colors else {
+            return Ok(None);
+        };
+
+        let near_colors = near_colors
+            .iter()
+            .map(|near_color| utils::common::parse_hex_color(near_color))
+            .collect::<Result<_>>()?;
+        Ok(Some(near_colors))
+    }
+}
+
+fn ensure_range_is_valid<T: PartialOrd + std::fmt::Display>(
+    key: &str,
+    range: &RangeInclusive<T>,
+) -> Result<()> {
+    ensure!(
+        range.start() <= range.end(),
+        "invalid range for `{}`: start should be <= end: {} > {}",
+        key,
+        range.start(),
+        range.end()
+    );
+    Ok(())
+}
+
+/// Absolute form of a path, with symbolic links resolved when it exists, and `..` removed
+fn normalize_path(path: &Path) -> Result<PathBuf> {
+    if let Ok(path) = fs::canonicalize(path) {
+        return Ok(path);
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const SCHEDULED_CONFIG: &str = r#"
+        root_path = "/tmp/pictures"
+        meta_path = "/tmp/pictures/metadatas.json"
+        default_profile = "day"
+
+        [filters]
+
+        [profiles.day]
+        [profiles.night]
+        [profiles.christmas]
+
+        [[schedule]]
+        profile = "christmas"
+        dates = "12-24..12-25"
+
+        [[schedule]]
+        profile = "night"
+        time = "20:00..06:00"
+    "#;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn first_active_schedule_entry_wins() {
+        let configuration = utils::common::parse_config(SCHEDULED_CONFIG).unwrap();
+        let resolve = |date| configuration.resolve_profile_name(None, at(date)).unwrap();
+
+        assert_eq!(resolve("2026-12-24 22:00"), Some("christmas"));
+        assert_eq!(resolve("2026-12-23 22:00"), Some("night"));
+        assert_eq!(resolve("2026-12-23 05:00"), Some("night"));
+    }
+
+    #[test]
+    fn default_profile_is_used_without_active_entry() {
+        let configuration = utils::common::parse_config(SCHEDULED_CONFIG).unwrap();
+        let profile = configuration
+            .resolve_profile_name(None, at("2026-12-23 12:00"))
+            .unwrap();
+        assert_eq!(profile, Some("day"));
+    }
+
+    #[test]
+    fn given_profile_takes_precedence_over_schedule() {
+        let mut configuration = utils::common::parse_config(SCHEDULED_CONFIG).unwrap();
+        let profile = configuration
+            .resolve_profile_name(Some("day"), at("2026-12-24 22:00"))
+            .unwrap();
+        assert_eq!(profile, Some("day"));
+
+        configuration.sources.insert(
+            String::from("default_profile"),
+            ValueSource::Environment("KANUMI_PROFILE"),
+        );
+        let profile = configuration
+            .resolve_profile_name(None, at("2026-12-24 22:00"))
+            .unwrap();
+        assert_eq!(profile, Some("day"));
+    }
+
+    #[test]
+    fn rejects_schedule_of_unknown_profile() {
+        let content = SCHEDULED_CONFIG.replace(r#"profile = "night""#, r#"profile = "nigth""#);
+        assert!(utils::common::parse_config(&content).is_err());
+    }
+
+    #[test]
+    fn rejects_undefined_daemon_score() {
+        let content = format!("{SCHEDULED_CONFIG}\n[scores.favorite]\n");
+        assert!(utils::common::parse_config(&content).is_err());
+
+        let content = format!("{content}\n[daemon]\nscore = \"favorite\"\n");
+        assert!(utils::common::parse_config(&content).is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_and_overlapping_roots() {
+        let config = |roots: &str| format!("{SCHEDULED_CONFIG}\n[roots]\n{roots}\n");
+
+        assert!(utils::common::parse_config(&config(r#"other = { path = "/tmp/other" }"#)).is_ok());
+        assert!(utils::common::parse_config(&config(r#"empty = { path = "" }"#)).is_err());
+        assert!(utils::common::parse_config(&config(
+            r#"nested = { path = "/tmp/pictures/nested" }"#
+        ))
+        .is_err());
+        assert!(utils::common::parse_config(&config(
+            r#"parent = { path = "/tmp/other/../pictures" }"#
+        ))
+        .is_err());
+
+        let relative = env::current_dir().unwrap().join("pictures");
+        let content = config(r#"relative = { path = "pictures/nested" }"#)
+            .replace("/tmp/pictures\"", &format!("{}\"", relative.display()));
+        assert!(utils::common::parse_config(&content).is_err());
+    }
+}
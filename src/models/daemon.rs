@@ -0,0 +1,40 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Command sent to the daemon through its control socket, one JSON object per line
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    Next,
+    Prev,
+    Pause,
+    Resume,
+    Rate {
+        /// Name of the score. Default is the one set in configuration
+        score: Option<String>,
+        value: u8,
+    },
+    Status,
+}
+
+/// Answer of the daemon to a request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonResponse {
+    Status(DaemonStatus),
+    Error(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DaemonStatus {
+    /// Path of the wallpaper currently applied
+    pub current: Option<PathBuf>,
+    pub paused: bool,
+    /// Number of images matching the filters
+    pub images: usize,
+    /// Name of the profile whose filters the images were matched with
+    pub profile: Option<String>,
+    /// When the next wallpaper is applied, unless paused
+    pub next_change: Option<DateTime<Local>>,
+}
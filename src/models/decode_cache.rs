@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeStatus {
+    #[serde(rename = "ok")]
+    Ok,
+    #[serde(rename = "truncated")]
+    Truncated,
+    #[serde(rename = "corrupt")]
+    Corrupt,
+    #[serde(rename = "unsupported")]
+    Unsupported,
+    #[serde(rename = "unreadable")]
+    Unreadable,
+}
+
+impl DecodeStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DecodeStatus::Ok => "ok",
+            DecodeStatus::Truncated => "truncated",
+            DecodeStatus::Corrupt => "corrupt",
+            DecodeStatus::Unsupported => "unsupported",
+            DecodeStatus::Unreadable => "unreadable",
+        }
+    }
+}
+
+/// Result of a full decoding of an image file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DecodeResult {
+    pub status: DecodeStatus,
+    /// Error returned by the decoder, if any
+    pub error: Option<String>,
+    /// Size of the file when it was decoded
+    pub size: u64,
+    /// Modification date of the file when it was decoded
+    pub modified: DateTime<Utc>,
+}
+
+/// Results of previous decodings, used to skip files that did not change since
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DecodeCache {
+    pub entries: HashMap<PathBuf, DecodeResult>,
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, f64::consts::PI};
+
+/// Scale factor between Glicko and logistic ratings, ln(10) / 400
+const Q: f64 = std::f64::consts::LN_10 / 400.0;
+
+const INITIAL_RATING: f64 = 1500.0;
+const INITIAL_DEVIATION: f64 = 350.0;
+
+/// Deviation never drops below this, so that ratings can still move after many duels
+const MIN_DEVIATION: f64 = 30.0;
+
+/// Glicko rating of an image, learned from duels against other images
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DuelRating {
+    pub rating: f64,
+    /// Uncertainty of the rating, high for images that had few duels
+    pub deviation: f64,
+    pub duels: u32,
+}
+
+impl Default for DuelRating {
+    fn default() -> DuelRating {
+        DuelRating {
+            rating: INITIAL_RATING,
+            deviation: INITIAL_DEVIATION,
+            duels: 0,
+        }
+    }
+}
+
+impl DuelRating {
+    /// Probability of winning against the opponent
+    pub fn expected_score(&self, opponent: &DuelRating) -> f64 {
+        let exponent =
+            -get_attenuation(opponent.deviation) * (self.rating - opponent.rating) / 400.0;
+        1.0 / (1.0 + 10f64.powf(exponent))
+    }
+
+    /// Rating after a duel against the opponent, with a score of 1 for a win, 0.5 for a draw and 0 for a loss
+    pub fn update(&self, opponent: &DuelRating, score: f64) -> DuelRating {
+        let attenuation = get_attenuation(opponent.deviation);
+        let expected = self.expected_score(opponent);
+        let precision = self.deviation.powi(-2) + self.get_information(opponent);
+
+        DuelRating {
+            rating: self.rating + Q / precision * attenuation * (score - expected),
+            deviation: precision.sqrt().recip().max(MIN_DEVIATION),
+            duels: self.duels + 1,
+        }
+    }
+
+    /// How much the variance of the rating would shrink after a duel against the opponent
+    pub fn get_variance_reduction(&self, opponent: &DuelRating) -> f64 {
+        let variance = self.deviation.powi(2);
+        variance - 1.0 / (1.0 / variance + self.get_information(opponent))
+    }
+
+    /// Fisher information brought by a duel against the opponent
+    fn get_information(&self, opponent: &DuelRating) -> f64 {
+        let expected = self.expected_score(opponent);
+        Q.powi(2) * get_attenuation(opponent.deviation).powi(2) * expected * (1.0 - expected)
+    }
+}
+
+/// Factor that reduces the weight of duels against opponents whose rating is uncertain
+fn get_attenuation(deviation: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * Q.powi(2) * deviation.powi(2) / PI.powi(2)).sqrt()
+}
+
+/// Ratings learned from duels, by score name and then by metadata ID
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DuelRatings {
+    pub scores: HashMap<String, HashMap<String, DuelRating>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> DuelRating {
+        DuelRating {
+            rating,
+            deviation,
+            duels: 0,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn expected_scores_are_symmetric() {
+        let (strong, weak) = (rating(1700.0, 80.0), rating(1400.0, 80.0));
+        assert_close(
+            strong.expected_score(&weak) + weak.expected_score(&strong),
+            1.0,
+        );
+        assert!(strong.expected_score(&weak) > 0.5);
+
+        let default = DuelRating::default();
+        assert_close(default.expected_score(&default), 0.5);
+    }
+
+    #[test]
+    fn expected_score_matches_glicko_example() {
+        // Example of Mark Glickman's description of the Glicko system
+        let player = rating(1500.0, 200.0);
+        assert_close(player.expected_score(&rating(1400.0, 30.0)), 0.639);
+        assert_close(player.expected_score(&rating(1550.0, 100.0)), 0.432);
+        assert_close(player.expected_score(&rating(1700.0, 300.0)), 0.303);
+    }
+
+    #[test]
+    fn updates_after_win_draw_and_loss() {
+        let default = DuelRating::default();
+
+        let win = default.update(&default, 1.0);
+        assert_close(win.rating, 1662.21);
+        assert_close(win.deviation, 290.23);
+        assert_eq!(win.duels, 1);
+
+        let draw = default.update(&default, 0.5);
+        assert_close(draw.rating, 1500.0);
+        assert_close(draw.deviation, 290.23);
+        assert_eq!(draw.duels, 1);
+
+        let loss = default.update(&default, 0.0);
+        assert_close(loss.rating, 1337.79);
+        assert_close(loss.deviation, 290.23);
+        assert_eq!(loss.duels, 1);
+    }
+
+    #[test]
+    fn upset_moves_rating_more_than_expected_result() {
+        let (player, favorite) = (rating(1500.0, 200.0), rating(1700.0, 50.0));
+        let upset = player.update(&favorite, 1.0).rating - player.rating;
+        let expected_loss = player.rating - player.update(&favorite, 0.0).rating;
+        assert!(upset > expected_loss);
+    }
+
+    #[test]
+    fn deviation_never_drops_below_minimum() {
+        let mut player = DuelRating::default();
+        for _ in 0..1000 {
+            player = player.update(&rating(1500.0, MIN_DEVIATION), 0.5);
+        }
+
+        assert_close(player.deviation, MIN_DEVIATION);
+        assert_eq!(player.duels, 1000);
+    }
+
+    #[test]
+    fn duels_against_certain_opponents_reduce_variance_more() {
+        let player = DuelRating::default();
+        let certain = rating(1500.0, 50.0);
+        let uncertain = rating(1500.0, 350.0);
+        assert!(
+            player.get_variance_reduction(&certain) > player.get_variance_reduction(&uncertain)
+        );
+        assert!(player.get_variance_reduction(&uncertain) > 0.0);
+    }
+}
@@ -1,10 +1,15 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use super::ConfigurationTheme;
 use crate::utils;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum ColorTheme {
     #[serde(rename = "light")]
     Light,
@@ -12,7 +17,39 @@ pub enum ColorTheme {
     Dark,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+impl ColorTheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorTheme::Light => "light",
+            ColorTheme::Dark => "dark",
+        }
+    }
+}
+
+impl FromStr for ColorTheme {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<ColorTheme> {
+        match input {
+            "light" => Ok(ColorTheme::Light),
+            "dark" => Ok(ColorTheme::Dark),
+            _ => bail!("unknown theme: `{input}`, expected one of: light, dark"),
+        }
+    }
+}
+
+/// Where the theme of an image comes from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeSource {
+    /// Classified by analysis, and classified again by the next one
+    #[serde(rename = "detected")]
+    Detected,
+    /// Chosen by hand, never replaced by analysis
+    #[serde(rename = "manual")]
+    Manual,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum Color {
     #[serde(rename = "red")]
     Red,
@@ -32,6 +69,63 @@ pub enum Color {
     Pink,
 }
 
+impl Color {
+    pub const ALL: [Color; 8] = [
+        Color::Red,
+        Color::Green,
+        Color::Blue,
+        Color::DarkGray,
+        Color::Black,
+        Color::White,
+        Color::Orange,
+        Color::Pink,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Color::Red => "red",
+            Color::Green => "green",
+            Color::Blue => "blue",
+            Color::DarkGray => "darkgray",
+            Color::Black => "black",
+            Color::White => "white",
+            Color::Orange => "orange",
+            Color::Pink => "pink",
+        }
+    }
+
+    /// sRGB value that extracted colors are compared against to find the nearest named color
+    pub fn reference_rgb(&self) -> [u8; 3] {
+        match self {
+            Color::Red => [215, 40, 40],
+            Color::Green => [60, 160, 70],
+            Color::Blue => [40, 90, 210],
+            Color::DarkGray => [75, 75, 80],
+            Color::Black => [10, 10, 12],
+            Color::White => [245, 245, 245],
+            Color::Orange => [240, 140, 30],
+            Color::Pink => [240, 130, 190],
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Color> {
+        match Color::ALL.into_iter().find(|color| color.name() == input) {
+            Some(color) => Ok(color),
+            None => {
+                let names: Vec<_> = Color::ALL.iter().map(Color::name).collect();
+                bail!(
+                    "unknown color: `{input}`, expected one of: {}",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ImageMeta {
     // blake3 hash
@@ -44,11 +138,31 @@ pub struct ImageMeta {
     pub scores: Vec<ImageScore>,
     pub tags: Vec<String>,
     pub theme: Option<ColorTheme>,
+    /// Confidence (0-1) of the detected theme. Not set when the theme was chosen by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme_confidence: Option<f64>,
+    /// Whether the theme was detected or chosen by hand. Metadata written before this field
+    /// existed tell a theme chosen by hand by its missing confidence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme_source: Option<ThemeSource>,
     pub colors: Vec<Color>,
+    /// Exact dominant colors of the image as `#rrggbb`, most dominant first
+    #[serde(default)]
+    pub palette: Vec<String>,
+    /// Difference hash of the image content, as hexadecimal, to find visually similar images
+    pub perceptual_hash: Option<String>,
+    /// Last time the image content was checked against its id
+    pub last_verified: Option<DateTime<Utc>>,
+    /// Name of the root the image belongs to. Only set when loaded, never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
 }
 
 impl ImageMeta {
-    pub fn create_from_image(image: &PathBuf) -> Result<ImageMeta> {
+    pub fn create_from_image(
+        image: &Path,
+        theme_settings: &ConfigurationTheme,
+    ) -> Result<ImageMeta> {
         let id = utils::common::compute_blake3_hash(image)?;
         let filename = image
             .file_name()
@@ -57,8 +171,9 @@ impl ImageMeta {
             .into_owned();
 
         let dimensions = utils::common::get_image_dims(image)?;
+        let perceptual_hash = utils::common::compute_perceptual_hash(image)?;
 
-        let meta = ImageMeta {
+        let mut meta = ImageMeta {
             id,
             path: image.to_path_buf(),
             title: filename.to_owned(),
@@ -68,11 +183,107 @@ impl ImageMeta {
             scores: vec![],
             tags: vec![],
             theme: None,
+            theme_confidence: None,
+            theme_source: None,
             colors: vec![],
+            palette: vec![],
+            perceptual_hash: Some(utils::common::format_perceptual_hash(perceptual_hash)),
+            last_verified: None,
+            root: None,
         };
 
+        meta.analyze(theme_settings)?;
         Ok(meta)
     }
+
+    /// Extract the dominant colors and detect the theme of the image, replacing previous results.
+    /// A theme chosen by hand is kept
+    pub fn analyze(&mut self, theme_settings: &ConfigurationTheme) -> Result<()> {
+        let sample = utils::palette::load_sample(&self.path)?;
+
+        let palette = utils::palette::extract_palette(&sample);
+        self.colors = utils::palette::get_dominant_colors(&palette);
+        self.palette = palette
+            .iter()
+            .map(|color| utils::palette::format_hex(color.rgb))
+            .collect();
+
+        if !self.has_manual_theme() {
+            let (theme, confidence) = utils::palette::classify_theme(&sample, theme_settings);
+            self.theme = theme;
+            self.theme_confidence = Some(confidence);
+            self.theme_source = Some(ThemeSource::Detected);
+        }
+
+        Ok(())
+    }
+
+    /// Whether the image went through analysis already
+    pub fn is_analyzed(&self) -> bool {
+        !self.palette.is_empty() && (self.theme.is_some() || self.theme_confidence.is_some())
+    }
+
+    pub fn has_manual_theme(&self) -> bool {
+        match self.theme_source {
+            Some(source) => self.theme.is_some() && source == ThemeSource::Manual,
+            None => self.theme.is_some() && self.theme_confidence.is_none(),
+        }
+    }
+
+    /// Set a theme chosen by hand, that analysis never replaces
+    pub fn set_manual_theme(&mut self, theme: ColorTheme) {
+        self.theme = Some(theme);
+        self.theme_confidence = None;
+        self.theme_source = Some(ThemeSource::Manual);
+    }
+
+    /// Set the value of a score, adding the score when the image does not have it yet
+    pub fn set_score(&mut self, name: &str, value: u8) {
+        match self.scores.iter_mut().find(|score| score.name == name) {
+            Some(score) => score.value = value,
+            None => self.scores.push(ImageScore {
+                name: name.to_owned(),
+                value,
+            }),
+        }
+    }
+
+    /// Complete this metadata with the data of a duplicate of the same image.
+    /// Existing values are kept, missing tags and scores are added
+    pub fn merge(&mut self, other: &ImageMeta) {
+        if self.description.is_empty() {
+            self.description = other.description.clone();
+        }
+
+        for tag in other.tags.iter() {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+
+        for score in other.scores.iter() {
+            if !self.scores.iter().any(|s| s.name == score.name) {
+                self.scores.push(score.clone());
+            }
+        }
+
+        if self.theme.is_none() || (!self.has_manual_theme() && other.has_manual_theme()) {
+            self.theme = other.theme;
+            self.theme_confidence = other.theme_confidence;
+            self.theme_source = other.theme_source;
+        }
+
+        if self.colors.is_empty() {
+            self.colors = other.colors.clone();
+            self.palette = other.palette.clone();
+        }
+
+        if self.perceptual_hash.is_none() {
+            self.perceptual_hash = other.perceptual_hash.clone();
+        }
+
+        self.last_verified = self.last_verified.max(other.last_verified);
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -80,3 +291,82 @@ pub struct ImageScore {
     pub name: String,
     pub value: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use serde_json::json;
+    use std::{env, fs, process};
+
+    fn meta(path: &Path) -> ImageMeta {
+        serde_json::from_value(json!({
+            "id": "0".repeat(64),
+            "path": path,
+            "title": "",
+            "description": "",
+            "width": 64,
+            "height": 64,
+            "scores": [],
+            "tags": [],
+            "colors": [],
+        }))
+        .unwrap()
+    }
+
+    fn analyze_image(name: &str, image: &RgbaImage, meta: &mut ImageMeta) {
+        let path = env::temp_dir().join(format!("kanumi-test-{}-{name}.png", process::id()));
+        image.save(&path).unwrap();
+        meta.path = path.clone();
+        let result = meta.analyze(&ConfigurationTheme::default());
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+    }
+
+    #[test]
+    fn analyzes_colors_of_image() {
+        let image = RgbaImage::from_fn(64, 64, |_, y| match y < 48 {
+            true => Rgba([215, 40, 40, 255]),
+            false => Rgba([40, 90, 210, 255]),
+        });
+
+        let mut meta = meta(Path::new(""));
+        analyze_image("two-colors", &image, &mut meta);
+        assert_eq!(meta.colors, vec![Color::Red, Color::Blue]);
+        assert_eq!(meta.palette, vec!["#d72828", "#285ad2"]);
+        assert_eq!(meta.theme_source, Some(ThemeSource::Detected));
+        assert!(meta.is_analyzed());
+    }
+
+    #[test]
+    fn keeps_manual_theme_when_analyzing() {
+        let black = RgbaImage::from_pixel(64, 64, Rgba([0, 0, 0, 255]));
+
+        let mut manual = meta(Path::new(""));
+        manual.set_manual_theme(ColorTheme::Light);
+        analyze_image("manual-theme", &black, &mut manual);
+        assert_eq!(manual.theme, Some(ColorTheme::Light));
+        assert_eq!(manual.theme_source, Some(ThemeSource::Manual));
+        assert_eq!(manual.theme_confidence, None);
+
+        let mut detected = meta(Path::new(""));
+        detected.theme = Some(ColorTheme::Light);
+        detected.theme_confidence = Some(0.8);
+        detected.theme_source = Some(ThemeSource::Detected);
+        analyze_image("detected-theme", &black, &mut detected);
+        assert_eq!(detected.theme, Some(ColorTheme::Dark));
+        assert_eq!(detected.theme_source, Some(ThemeSource::Detected));
+    }
+
+    #[test]
+    fn tells_manual_themes_of_older_metadata_by_missing_confidence() {
+        let mut meta = meta(Path::new(""));
+        assert!(!meta.has_manual_theme());
+
+        meta.theme = Some(ColorTheme::Dark);
+        assert!(meta.has_manual_theme());
+
+        meta.theme_confidence = Some(0.4);
+        assert!(!meta.has_manual_theme());
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name of the root defined by the top-level `root_path` of the configuration
+pub const DEFAULT_ROOT_NAME: &str = "default";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConfigurationRoot {
+    #[serde(rename = "path")]
+    pub path: PathBuf,
+
+    /// Metadata file of this root. Default is the top-level `meta_path`
+    #[serde(rename = "meta_path")]
+    pub metadata_path: Option<PathBuf>,
+
+    /// Only images matching at least one of these globs are part of the root
+    #[serde(rename = "include", default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Images matching any of these globs are not part of the root
+    #[serde(rename = "exclude", default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// Directory of images resolved from configuration, with compiled include/exclude rules
+#[derive(Debug, Clone)]
+pub struct ImageRoot {
+    pub name: String,
+    pub path: PathBuf,
+    pub metadata_path: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl ImageRoot {
+    pub fn new(
+        name: &str,
+        root: &ConfigurationRoot,
+        default_metadata_path: &Path,
+    ) -> Result<ImageRoot> {
+        let include = match root.include.is_empty() {
+            true => None,
+            false => Some(build_glob_set(&root.include)?),
+        };
+
+        Ok(ImageRoot {
+            name: name.to_owned(),
+            path: root.path.clone(),
+            metadata_path: root
+                .metadata_path
+                .clone()
+                .unwrap_or(default_metadata_path.to_path_buf()),
+            include,
+            exclude: build_glob_set(&root.exclude)?,
+        })
+    }
+
+    /// Whether the path is inside the root directory and matches its include/exclude rules
+    pub fn contains(&self, path: &Path) -> bool {
+        let Ok(relative_path) = path.strip_prefix(&self.path) else {
+            return false;
+        };
+
+        if let Some(include) = &self.include {
+            if !include.is_match(relative_path) {
+                return false;
+            }
+        }
+
+        !self.exclude.is_match(relative_path)
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter() {
+        let glob = Glob::new(pattern).context(format!("invalid glob pattern: `{pattern}`"))?;
+        builder.add(glob);
+    }
+
+    Ok(builder.build()?)
+}
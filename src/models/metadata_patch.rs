@@ -0,0 +1,48 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::{image_meta::ImageScore, ColorTheme, Configuration, ImageMeta};
+
+/// Fields of a metadata that can be changed by hand, the ones left out are kept
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataPatch {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub scores: Option<Vec<ImageScore>>,
+    pub theme: Option<ColorTheme>,
+}
+
+impl MetadataPatch {
+    /// Check that the scores are defined and within their range
+    pub fn validate(&self, configuration: &Configuration) -> Result<()> {
+        for score in self.scores.iter().flatten() {
+            configuration.validate_score(&score.name, score.value)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&self, meta: &mut ImageMeta) {
+        if let Some(title) = &self.title {
+            meta.title = title.clone();
+        }
+
+        if let Some(description) = &self.description {
+            meta.description = description.clone();
+        }
+
+        if let Some(tags) = &self.tags {
+            meta.tags = tags.clone();
+        }
+
+        if let Some(scores) = &self.scores {
+            meta.scores = scores.clone();
+        }
+
+        if let Some(theme) = self.theme {
+            meta.set_manual_theme(theme);
+        }
+    }
+}
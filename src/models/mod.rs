@@ -1,7 +1,35 @@
 pub mod configuration;
+#[cfg(unix)]
+pub mod daemon;
+pub mod decode_cache;
+pub mod duel_rating;
 pub mod image_meta;
+pub mod image_root;
+pub mod metadata_patch;
+pub mod schedule;
 pub mod score_filter;
+pub mod thumbnail_size;
 
 pub use self::configuration::Configuration;
+pub use self::configuration::ConfigurationOverrides;
+pub use self::configuration::ConfigurationTheme;
+pub use self::configuration::ConfigurationWalker;
+pub use self::configuration::ValueSource;
+#[cfg(unix)]
+pub use self::daemon::DaemonRequest;
+#[cfg(unix)]
+pub use self::daemon::DaemonResponse;
+#[cfg(unix)]
+pub use self::daemon::DaemonStatus;
+pub use self::decode_cache::DecodeCache;
+pub use self::decode_cache::DecodeResult;
+pub use self::decode_cache::DecodeStatus;
+pub use self::duel_rating::DuelRating;
+pub use self::duel_rating::DuelRatings;
+pub use self::image_meta::Color;
+pub use self::image_meta::ColorTheme;
 pub use self::image_meta::ImageMeta;
+pub use self::image_root::ImageRoot;
+pub use self::metadata_patch::MetadataPatch;
 pub use self::score_filter::ScoreFilter;
+pub use self::thumbnail_size::ThumbnailSize;
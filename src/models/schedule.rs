@@ -0,0 +1,213 @@
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// Profile that is selected while the date and time match all of the given conditions
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScheduleEntry {
+    #[serde(rename = "profile")]
+    pub profile: String,
+
+    /// Time window as `HH:MM..HH:MM`, wrapping around midnight when the end is before the start
+    #[serde(rename = "time", default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+
+    /// Days of the week (e.g. `mon`, `sat`)
+    #[serde(rename = "weekdays", default, skip_serializing_if = "Vec::is_empty")]
+    pub weekdays: Vec<String>,
+
+    /// Date range as `MM-DD..MM-DD`, wrapping around the new year when the end is before the start
+    #[serde(rename = "dates", default, skip_serializing_if = "Option::is_none")]
+    pub dates: Option<String>,
+}
+
+impl ScheduleEntry {
+    pub fn validate(&self, prefix: &str) -> Result<()> {
+        if let Some(time) = &self.time {
+            if let Err(e) = parse_time_window(time) {
+                bail!("invalid `{prefix}.time`: {e}");
+            }
+        }
+
+        for weekday in self.weekdays.iter() {
+            if let Err(e) = parse_weekday(weekday) {
+                bail!("invalid `{prefix}.weekdays`: {e}");
+            }
+        }
+
+        if let Some(dates) = &self.dates {
+            if let Err(e) = parse_date_range(dates) {
+                bail!("invalid `{prefix}.dates`: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the entry is active at the given local date and time
+    pub fn is_active(&self, at: NaiveDateTime) -> Result<bool> {
+        if let Some(time) = &self.time {
+            let (start, end) = parse_time_window(time)?;
+            if !is_in_window(at.time(), start, end) {
+                return Ok(false);
+            }
+        }
+
+        if !self.weekdays.is_empty() {
+            let mut weekdays = vec![];
+            for weekday in self.weekdays.iter() {
+                weekdays.push(parse_weekday(weekday)?);
+            }
+
+            if !weekdays.contains(&at.weekday()) {
+                return Ok(false);
+            }
+        }
+
+        if let Some(dates) = &self.dates {
+            let (start, end) = parse_date_range(dates)?;
+            if !is_in_window((at.month(), at.day()), start, end) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Whether the value is between start and end included, wrapping around when end < start
+fn is_in_window<T: PartialOrd>(value: T, start: T, end: T) -> bool {
+    match start <= end {
+        true => start <= value && value <= end,
+        false => value >= start || value <= end,
+    }
+}
+
+fn parse_time_window(input: &str) -> Result<(NaiveTime, NaiveTime)> {
+    let (start, end) = split_window(input)?;
+    let parse_time = |time: &str| {
+        NaiveTime::parse_from_str(time, "%H:%M")
+            .context(format!("expected time as `HH:MM` but got: `{time}`"))
+    };
+
+    Ok((parse_time(start)?, parse_time(end)?))
+}
+
+fn parse_date_range(input: &str) -> Result<((u32, u32), (u32, u32))> {
+    let (start, end) = split_window(input)?;
+    let parse_date = |date: &str| -> Result<(u32, u32)> {
+        // Leap year, so that `02-29` is accepted
+        let full_date = NaiveDate::parse_from_str(&format!("2000-{date}"), "%Y-%m-%d")
+            .context(format!("expected date as `MM-DD` but got: `{date}`"))?;
+        Ok((full_date.month(), full_date.day()))
+    };
+
+    Ok((parse_date(start)?, parse_date(end)?))
+}
+
+fn parse_weekday(input: &str) -> Result<Weekday> {
+    match input.trim().parse::<Weekday>() {
+        Ok(weekday) => Ok(weekday),
+        Err(_) => bail!("expected day of the week (e.g. `mon`) but got: `{input}`"),
+    }
+}
+
+fn split_window(input: &str) -> Result<(&str, &str)> {
+    match input.split_once("..") {
+        Some((start, end)) => Ok((start.trim(), end.trim())),
+        None => bail!("expected `start..end` but got: `{input}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn entry(time: Option<&str>, weekdays: &[&str], dates: Option<&str>) -> ScheduleEntry {
+        ScheduleEntry {
+            profile: String::from("test"),
+            time: time.map(String::from),
+            weekdays: weekdays.iter().map(|weekday| weekday.to_string()).collect(),
+            dates: dates.map(String::from),
+        }
+    }
+
+    #[test]
+    fn window_includes_both_ends() {
+        assert!(is_in_window(6, 6, 11));
+        assert!(is_in_window(11, 6, 11));
+        assert!(!is_in_window(5, 6, 11));
+        assert!(!is_in_window(12, 6, 11));
+        assert!(is_in_window(3, 3, 3));
+    }
+
+    #[test]
+    fn window_wraps_around_when_end_is_before_start() {
+        assert!(is_in_window(22, 20, 6));
+        assert!(is_in_window(20, 20, 6));
+        assert!(is_in_window(0, 20, 6));
+        assert!(is_in_window(6, 20, 6));
+        assert!(!is_in_window(7, 20, 6));
+        assert!(!is_in_window(19, 20, 6));
+    }
+
+    #[test]
+    fn time_window_across_midnight() {
+        let night = entry(Some("20:00..06:00"), &[], None);
+        assert!(night.is_active(at("2026-03-10 23:30")).unwrap());
+        assert!(night.is_active(at("2026-03-11 00:00")).unwrap());
+        assert!(night.is_active(at("2026-03-11 06:00")).unwrap());
+        assert!(!night.is_active(at("2026-03-11 06:01")).unwrap());
+        assert!(!night.is_active(at("2026-03-11 12:00")).unwrap());
+    }
+
+    #[test]
+    fn weekdays_are_those_of_the_current_date() {
+        let friday_night = entry(Some("22:00..02:00"), &["fri"], None);
+        assert!(friday_night.is_active(at("2026-10-16 23:00")).unwrap());
+        assert!(!friday_night.is_active(at("2026-10-17 01:00")).unwrap());
+        assert!(!friday_night.is_active(at("2026-10-15 23:00")).unwrap());
+    }
+
+    #[test]
+    fn date_range_across_new_year() {
+        let holidays = entry(None, &[], Some("12-20..01-05"));
+        assert!(holidays.is_active(at("2026-12-20 00:00")).unwrap());
+        assert!(holidays.is_active(at("2026-12-31 12:00")).unwrap());
+        assert!(holidays.is_active(at("2027-01-05 23:59")).unwrap());
+        assert!(!holidays.is_active(at("2027-01-06 00:00")).unwrap());
+        assert!(!holidays.is_active(at("2026-07-14 12:00")).unwrap());
+    }
+
+    #[test]
+    fn leap_day_is_accepted() {
+        let leap_day = entry(None, &[], Some("02-29..02-29"));
+        assert!(leap_day.validate("schedule[0]").is_ok());
+        assert!(leap_day.is_active(at("2028-02-29 12:00")).unwrap());
+        assert!(!leap_day.is_active(at("2027-02-28 12:00")).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let invalid_entries = [
+            entry(Some("20:00"), &[], None),
+            entry(Some("25:00..06:00"), &[], None),
+            entry(Some("8h..9h"), &[], None),
+            entry(None, &["someday"], None),
+            entry(None, &[], Some("13-01..12-31")),
+            entry(None, &[], Some("02-30..03-01")),
+            entry(None, &[], Some("12-20")),
+        ];
+
+        for invalid_entry in invalid_entries.iter() {
+            assert!(
+                invalid_entry.validate("schedule[0]").is_err(),
+                "{invalid_entry:?} should be invalid"
+            );
+        }
+    }
+}
@@ -10,4 +10,7 @@ pub struct ScoreFilter {
     /// If true, images that do not specify a score values for this filter will be matched. Default is: false
     #[serde(default)]
     pub allow_unscored: bool,
+    /// Value assumed for images that do not have the score, from its definition in configuration
+    #[serde(skip)]
+    pub default: Option<u8>,
 }
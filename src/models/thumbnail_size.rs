@@ -0,0 +1,52 @@
+use anyhow::{bail, Result};
+use std::str::FromStr;
+
+/// Size of thumbnails, as defined by the freedesktop thumbnail specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThumbnailSize {
+    Normal,
+    Large,
+    XLarge,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 3] = [
+        ThumbnailSize::Normal,
+        ThumbnailSize::Large,
+        ThumbnailSize::XLarge,
+    ];
+
+    /// Name of the size, which is also the name of its cache directory
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThumbnailSize::Normal => "normal",
+            ThumbnailSize::Large => "large",
+            ThumbnailSize::XLarge => "x-large",
+        }
+    }
+
+    /// Maximum width and height of thumbnails, in pixels
+    pub fn pixels(&self) -> u32 {
+        match self {
+            ThumbnailSize::Normal => 128,
+            ThumbnailSize::Large => 256,
+            ThumbnailSize::XLarge => 512,
+        }
+    }
+}
+
+impl FromStr for ThumbnailSize {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<ThumbnailSize> {
+        match ThumbnailSize::ALL
+            .into_iter()
+            .find(|size| size.name() == input)
+        {
+            Some(size) => Ok(size),
+            None => {
+                bail!("unknown thumbnail size: `{input}`, expected one of: normal, large, x-large")
+            }
+        }
+    }
+}
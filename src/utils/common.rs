@@ -1,19 +1,44 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use directories::ProjectDirs;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use image::{ImageError, ImageFormat, ImageReader};
 use log::{debug, info, warn};
 use std::{
+    collections::{HashMap, HashSet},
     env,
-    fs::{self, File},
-    io::Read,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
     ops::RangeInclusive,
     path::{Path, PathBuf},
+    thread, time,
 };
-use walkdir::{DirEntry, WalkDir};
 
-use crate::models::{Configuration, ImageMeta, ScoreFilter};
+use crate::models::{
+    Color, ColorTheme, Configuration, ConfigurationOverrides, ConfigurationWalker, DecodeCache,
+    DecodeResult, DecodeStatus, DuelRatings, ImageMeta, ImageRoot, ScoreFilter, ThumbnailSize,
+    ValueSource,
+};
 
 pub const APP_NAME: &str = "kanumi";
 pub const CONFIG_VAR: &str = "KANUMI_CONFIG";
+pub const ROOT_VAR: &str = "KANUMI_ROOT";
+pub const META_PATH_VAR: &str = "KANUMI_META_PATH";
+pub const PROFILE_VAR: &str = "KANUMI_PROFILE";
+pub const TOKEN_VAR: &str = "KANUMI_TOKEN";
+pub const IGNORE_FILE_NAME: &str = ".kanumiignore";
+
+const DECODE_CACHE_FILE: &str = "decode_cache.json";
+const DUEL_RATINGS_FILE: &str = "duel_ratings.json";
+#[cfg(unix)]
+const DAEMON_SOCKET_FILE: &str = "daemon.sock";
+
+/// Number of bytes read to guess the format of a file
+const SNIFF_LENGTH: usize = 64;
+
+/// Number of bytes read at the end of a JPEG file to look for its end of image marker
+const JPEG_TAIL_LENGTH: u64 = 1024;
 
 pub fn get_config_dir() -> Result<PathBuf> {
     if let Ok(config_var) = env::var(CONFIG_VAR) {
@@ -36,12 +61,49 @@ pub fn get_config_dir() -> Result<PathBuf> {
     bail!("could not get config directory")
 }
 
+pub fn get_cache_dir() -> Result<PathBuf> {
+    if let Some(proj_dirs) = ProjectDirs::from("", "", APP_NAME) {
+        let cache_dir = proj_dirs.cache_dir();
+        debug!("get cache dir from proj dirs: {}", cache_dir.display());
+        return Ok(cache_dir.to_path_buf());
+    }
+
+    bail!("could not get cache directory")
+}
+
+pub fn get_data_dir() -> Result<PathBuf> {
+    if let Some(proj_dirs) = ProjectDirs::from("", "", APP_NAME) {
+        let data_dir = proj_dirs.data_dir();
+        debug!("get data dir from proj dirs: {}", data_dir.display());
+        return Ok(data_dir.to_path_buf());
+    }
+
+    bail!("could not get data directory")
+}
+
+/// Get the path of the daemon control socket: the configured one, or one in the runtime
+/// directory, falling back to the cache directory
+#[cfg(unix)]
+pub fn get_socket_path(configuration: &Configuration) -> Result<PathBuf> {
+    if let Some(socket_path) = &configuration.daemon.socket_path {
+        return Ok(socket_path.clone());
+    }
+
+    if let Some(runtime_dir) = ProjectDirs::from("", "", APP_NAME)
+        .as_ref()
+        .and_then(ProjectDirs::runtime_dir)
+    {
+        return Ok(runtime_dir.join(DAEMON_SOCKET_FILE));
+    }
+
+    Ok(get_cache_dir()?.join(DAEMON_SOCKET_FILE))
+}
+
 pub fn get_config_file() -> Result<PathBuf> {
     Ok(get_config_dir()?.join("config.toml"))
 }
 
-pub fn create_config_file() -> Result<()> {
-    let file_path = get_config_file()?;
+pub fn create_config_file(file_path: &Path) -> Result<()> {
     info!("create config file: `{}`", file_path.to_string_lossy());
 
     if let Some(config_dir) = file_path.parent() {
@@ -51,18 +113,82 @@ pub fn create_config_file() -> Result<()> {
     let default_config = Configuration::create_default();
     let toml = default_config.to_toml_str()?;
 
-    fs::write(&file_path, toml)?;
+    fs::write(file_path, toml)?;
     Ok(())
 }
 
-pub fn load_config(path: PathBuf) -> Result<Configuration> {
+pub fn load_config(path: &Path, overrides: &ConfigurationOverrides) -> Result<Configuration> {
     let content = fs::read_to_string(path)?;
+    let mut config = parse_config(&content)?;
+
+    let file_keys: toml::Table = toml::from_str(&content)?;
+    if let toml::Value::Table(config_keys) = toml::Value::try_from(&config)? {
+        for key in config_keys.keys() {
+            let source = match file_keys.contains_key(key) {
+                true => ValueSource::ConfigFile,
+                false => ValueSource::Default,
+            };
+            config.sources.insert(key.to_owned(), source);
+        }
+    }
+
+    if let Some((root_images_dir, source)) = &overrides.root_images_dir {
+        info!(
+            "override from {}: root_path = {}",
+            source,
+            root_images_dir.display()
+        );
+        config.root_images_dir = expand_path(root_images_dir)?;
+        config
+            .sources
+            .insert(String::from("root_path"), source.clone());
+    }
 
+    if let Some((metadata_path, source)) = &overrides.metadata_path {
+        info!(
+            "override from {}: meta_path = {}",
+            source,
+            metadata_path.display()
+        );
+        config.metadata_path = expand_path(metadata_path)?;
+        config
+            .sources
+            .insert(String::from("meta_path"), source.clone());
+    }
+
+    if let Ok(profile) = env::var(PROFILE_VAR) {
+        info!("override from env: {} = {}", PROFILE_VAR, profile);
+        config.default_profile = Some(profile);
+        config.sources.insert(
+            String::from("default_profile"),
+            ValueSource::Environment(PROFILE_VAR),
+        );
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+pub fn parse_config(content: &str) -> Result<Configuration> {
     info!("parsing config toml");
-    let config: Configuration = toml::from_str(&content)?;
+    let mut config: Configuration = toml::from_str(content)?;
+    config.expand_paths()?;
+    config.validate()?;
     Ok(config)
 }
 
+/// Expand `~` and environment variables (`$VAR` or `${VAR}`) in a path
+pub fn expand_path(path: &Path) -> Result<PathBuf> {
+    let path_str = path
+        .to_str()
+        .context(format!("path should be a valid string: {}", path.display()))?;
+
+    let expanded = shellexpand::full(path_str)
+        .context(format!("failed to expand path: {}", path.display()))?;
+
+    Ok(PathBuf::from(expanded.as_ref()))
+}
+
 pub fn parse_score_filters(input: &str) -> Result<ScoreFilter> {
     let mut allow_unscored = false;
     let mut input = input.to_string();
@@ -85,11 +211,28 @@ pub fn parse_score_filters(input: &str) -> Result<ScoreFilter> {
         name: key,
         range,
         allow_unscored,
+        default: None,
     };
 
     Ok(score_filter)
 }
 
+pub fn parse_color(input: &str) -> Result<Color> {
+    input.trim().to_lowercase().parse()
+}
+
+pub fn parse_theme(input: &str) -> Result<ColorTheme> {
+    input.trim().to_lowercase().parse()
+}
+
+pub fn parse_thumbnail_size(input: &str) -> Result<ThumbnailSize> {
+    input.trim().to_lowercase().parse()
+}
+
+pub fn parse_hex_color(input: &str) -> Result<[u8; 3]> {
+    super::palette::parse_hex(input)
+}
+
 pub fn parse_range(input: &str) -> Result<RangeInclusive<usize>> {
     if let Ok(num) = input.parse::<usize>() {
         return Ok(num..=num);
@@ -129,27 +272,174 @@ pub fn parse_range(input: &str) -> Result<RangeInclusive<usize>> {
     }
 }
 
-pub fn get_image_dims(image: &PathBuf) -> Result<(u32, u32)> {
-    Ok(image::image_dimensions(image)?)
+/// Parse dimensions given as `WIDTHxHEIGHT` (e.g. `80x40`)
+pub fn parse_dimensions(input: &str) -> Result<(u32, u32)> {
+    let Some((width, height)) = input.trim().split_once('x') else {
+        bail!("expected dimensions as WIDTHxHEIGHT but got: `{input}`");
+    };
+
+    let width: u32 = width.parse().context(format!("invalid width: `{width}`"))?;
+    let height: u32 = height
+        .parse()
+        .context(format!("invalid height: `{height}`"))?;
+    ensure!(
+        width > 0 && height > 0,
+        "dimensions should be positive, got: `{input}`"
+    );
+    Ok((width, height))
+}
+
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let unit_start = input.find(|c: char| !c.is_ascii_digit()).context(format!(
+        "missing duration unit (s, m, h, d, w) in: `{}`",
+        input
+    ))?;
+
+    let (value, unit) = input.split_at(unit_start);
+    let value = match value.parse::<i64>() {
+        Ok(value) => value,
+        Err(e) => bail!("failed to parse number: `{}`", e),
+    };
+
+    let duration = match unit {
+        "s" => Duration::try_seconds(value),
+        "m" => Duration::try_minutes(value),
+        "h" => Duration::try_hours(value),
+        "d" => Duration::try_days(value),
+        "w" => Duration::try_weeks(value),
+        _ => bail!("expected duration unit (s, m, h, d, w) but got: `{}`", unit),
+    };
+
+    match duration {
+        Some(duration) => Ok(duration),
+        None => bail!("duration is too long: `{}`", input),
+    }
+}
+
+/// Parse a local date and time, such as `2026-12-24T21:00`, or a date alone for its midnight
+pub fn parse_datetime(input: &str) -> Result<NaiveDateTime> {
+    let input = input.trim();
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(input, format) {
+            return Ok(datetime);
+        }
+    }
+
+    match NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_time(NaiveTime::MIN)),
+        Err(_) => bail!("expected date and time as `YYYY-MM-DDTHH:MM` but got: `{input}`"),
+    }
+}
+
+pub fn parse_size(input: &str) -> Result<u64> {
+    let input = input.trim();
+    let unit_start = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+
+    let (value, unit) = input.split_at(unit_start);
+    let value = match value.parse::<u64>() {
+        Ok(value) => value,
+        Err(e) => bail!("failed to parse number: `{}`", e),
+    };
+
+    let multiplier: u64 = match unit.to_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        _ => bail!("expected size unit (B, KB, MB, GB, TB) but got: `{}`", unit),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .context(format!("size is too large: `{}`", input))
+}
+
+/// Get the dimensions of an image, guessing its format from content rather than extension
+pub fn get_image_dims(image: &Path) -> Result<(u32, u32)> {
+    let reader = ImageReader::open(image)?.with_guessed_format()?;
+    Ok(reader.into_dimensions()?)
+}
+
+/// Compute the difference hash (dHash) of an image: each bit tells whether a pixel of a 9x8
+/// grayscale thumbnail is darker than its right neighbour. Resizing or re-compressing an image
+/// barely changes its hash
+pub fn compute_perceptual_hash(image: &Path) -> Result<u64> {
+    let image = ImageReader::open(image)?.with_guessed_format()?.decode()?;
+    let pixels = image.thumbnail_exact(9, 8).to_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] < pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Ok(hash)
+}
+
+pub fn format_perceptual_hash(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+pub fn parse_perceptual_hash(input: &str) -> Result<u64> {
+    u64::from_str_radix(input, 16).context(format!("invalid perceptual hash: `{input}`"))
+}
+
+/// Number of differing bits between two perceptual hashes
+pub fn get_hash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Guess the format of an image from the magic bytes at the start of the file
+pub fn sniff_image_format(path: &Path) -> Result<Option<ImageFormat>> {
+    let mut buffer = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut buffer)?;
+
+    Ok(image::guess_format(&buffer).ok())
+}
+
+/// Get the image format associated to the extension of a file
+pub fn get_extension_format(path: &Path) -> Option<ImageFormat> {
+    ImageFormat::from_path(path).ok()
+}
+
+pub fn get_format_name(format: Option<ImageFormat>) -> &'static str {
+    format
+        .and_then(|format| format.extensions_str().first().copied())
+        .unwrap_or("unknown")
 }
 
 pub fn image_matches_dims(
-    image: &PathBuf,
+    image: &Path,
     width_range: &Option<RangeInclusive<usize>>,
     height_range: &Option<RangeInclusive<usize>>,
 ) -> bool {
     debug!("checking dimensions for: {}", image.display());
-    let dimensions = image::image_dimensions(image);
-    if dimensions.is_err() {
-        warn!(
-            "failed to check dimensions for: {}, error: {}",
-            image.display(),
-            dimensions.unwrap_err()
-        );
-        return false;
-    }
+    let dimensions = match get_image_dims(image) {
+        Ok(dimensions) => dimensions,
+        Err(e) => {
+            warn!(
+                "failed to check dimensions for: {}, error: {}",
+                image.display(),
+                e
+            );
+            return false;
+        }
+    };
 
-    let dimensions = dimensions.unwrap();
     let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
 
     if let Some(width_range) = width_range {
@@ -179,6 +469,9 @@ pub fn image_score_matches(meta: &ImageMeta, score_filter: &ScoreFilter) -> bool
     }
 
     score_filter.allow_unscored
+        || score_filter
+            .default
+            .is_some_and(|default| score_filter.range.contains(&usize::from(default)))
 }
 
 pub fn load_image_metas(meta_file_path: &Path) -> Result<Vec<ImageMeta>> {
@@ -187,25 +480,365 @@ pub fn load_image_metas(meta_file_path: &Path) -> Result<Vec<ImageMeta>> {
     Ok(metas)
 }
 
-pub fn get_all_images(base_directory: &Path) -> Result<Vec<PathBuf>> {
-    Ok(WalkDir::new(base_directory)
+/// Get the image roots to work with, making sure that their directory and metadata file exist
+pub fn get_image_roots(
+    configuration: &Configuration,
+    root_name: Option<&str>,
+) -> Result<Vec<ImageRoot>> {
+    let roots = configuration.image_roots(root_name)?;
+
+    for root in roots.iter() {
+        ensure!(
+            root.path.exists(),
+            "could not find images directory of root `{}`: {}",
+            root.name,
+            root.path.display()
+        );
+        ensure!(
+            root.metadata_path.exists(),
+            "could not find metadata file of root `{}`: {}",
+            root.name,
+            root.metadata_path.display()
+        );
+    }
+
+    Ok(roots)
+}
+
+/// Get the distinct metadata files used by the given roots
+pub fn get_metadata_paths(roots: &[ImageRoot]) -> Vec<&Path> {
+    let mut metadata_paths: Vec<&Path> = vec![];
+    for root in roots.iter() {
+        if !metadata_paths.contains(&root.metadata_path.as_path()) {
+            metadata_paths.push(&root.metadata_path);
+        }
+    }
+
+    metadata_paths
+}
+
+/// Load every metadata stored in the metadata files of the given roots
+pub fn load_all_image_metas(roots: &[ImageRoot]) -> Result<Vec<ImageMeta>> {
+    let mut metas = vec![];
+    for metadata_path in get_metadata_paths(roots) {
+        debug!("loading metadatas from: {}", metadata_path.display());
+        metas.extend(load_image_metas(metadata_path)?);
+    }
+
+    Ok(metas)
+}
+
+/// Load the metadatas of images that belong to the given roots, tagged with their root name
+pub fn load_root_metas(roots: &[ImageRoot]) -> Result<Vec<ImageMeta>> {
+    let (metas, ignored): (Vec<_>, Vec<_>) = load_tagged_metas(roots)?
+        .into_iter()
+        .partition(|meta| meta.root.is_some());
+
+    if !ignored.is_empty() {
+        warn!(
+            "ignoring {} metadatas outside of the roots, see `kanumi metadata check`",
+            ignored.len()
+        );
+    }
+
+    Ok(metas)
+}
+
+/// Load every metadata of the metadata files of the given roots, tagged with the name of the
+/// root they belong to, if any
+pub fn load_tagged_metas(roots: &[ImageRoot]) -> Result<Vec<ImageMeta>> {
+    let metas = load_all_image_metas(roots)?
         .into_iter()
-        .filter_map(Result::ok)
-        .filter(is_image_file)
-        .map(|entry| entry.path().to_owned())
-        .collect())
+        .map(|mut meta| {
+            meta.root = roots
+                .iter()
+                .find(|root| root.contains(&meta.path))
+                .map(|root| root.name.clone());
+            meta
+        })
+        .collect();
+
+    Ok(metas)
+}
+
+/// Load metadatas, apply `update` to them and write them back while holding the metadata lock
+pub fn update_image_metas<F>(meta_file_path: &Path, update: F) -> Result<()>
+where
+    F: FnOnce(&mut Vec<ImageMeta>) -> Result<()>,
+{
+    let _lock = MetadataLock::acquire(meta_file_path)?;
+
+    let mut metas = load_image_metas(meta_file_path)?;
+    update(&mut metas)?;
+    for meta in metas.iter_mut() {
+        meta.root = None;
+    }
+
+    let json = serde_json::to_string_pretty(&metas)?;
+    let tmp_path = append_to_path(meta_file_path, ".tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, meta_file_path)?;
+
+    info!(
+        "saved {} metadatas to: {}",
+        metas.len(),
+        meta_file_path.display()
+    );
+    Ok(())
+}
+
+/// Exclusive lock on the metadata file, released when dropped
+struct MetadataLock {
+    path: PathBuf,
+}
+
+impl MetadataLock {
+    const RETRIES: u32 = 50;
+    const RETRY_DELAY: time::Duration = time::Duration::from_millis(100);
+
+    fn acquire(meta_file_path: &Path) -> Result<MetadataLock> {
+        let path = append_to_path(meta_file_path, ".lock");
+
+        for _ in 0..Self::RETRIES {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => {
+                    debug!("acquired metadata lock: {}", path.display());
+                    return Ok(MetadataLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    debug!("metadata lock is busy, retrying...");
+                    thread::sleep(Self::RETRY_DELAY);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        bail!(
+            "metadata file is locked by another process, remove `{}` if this is not the case",
+            path.display()
+        )
+    }
+}
+
+impl Drop for MetadataLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(
+                "failed to release metadata lock {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
 }
 
-fn is_image_file(entry: &DirEntry) -> bool {
-    if let Some(file_name) = entry.file_name().to_str() {
-        return file_name.to_lowercase().ends_with(".gif")
-            || file_name.to_lowercase().ends_with(".jpeg")
-            || file_name.to_lowercase().ends_with(".jpg")
-            || file_name.to_lowercase().ends_with(".png")
-            || file_name.to_lowercase().ends_with(".webp");
+fn append_to_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut path: OsString = path.into();
+    path.push(suffix);
+    path.into()
+}
+
+/// Walk through a directory to find images, honouring ignore files and walker settings
+pub fn get_all_images(base_directory: &Path, walker: &ConfigurationWalker) -> Result<Vec<PathBuf>> {
+    let formats = walker.image_formats()?;
+    let mut overrides = OverrideBuilder::new(base_directory);
+    for pattern in walker.exclude.iter() {
+        overrides
+            .add(&format!("!{pattern}"))
+            .context(format!("invalid exclude pattern: `{pattern}`"))?;
+    }
+
+    let walk = WalkBuilder::new(base_directory)
+        .standard_filters(false)
+        .hidden(!walker.include_hidden)
+        .follow_links(walker.follow_symlinks)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .overrides(overrides.build()?)
+        .build();
+
+    let mut images = vec![];
+    for entry in walk {
+        match entry {
+            Ok(entry) => {
+                let is_dir = entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_dir());
+                if !is_dir && is_image_file(entry.path(), &formats) {
+                    images.push(entry.into_path());
+                }
+            }
+            // Symbolic link cycles are reported here
+            Err(e) => warn!(
+                "skipping entry while walking {}: {}",
+                base_directory.display(),
+                e
+            ),
+        }
     }
 
-    false
+    Ok(images)
+}
+
+/// Get all the images that belong to the given root
+pub fn get_root_images(root: &ImageRoot, walker: &ConfigurationWalker) -> Result<Vec<PathBuf>> {
+    let mut images = get_all_images(&root.path, walker)?;
+    images.retain(|image| root.contains(image));
+    Ok(images)
+}
+
+/// Whether the file is an image of an accepted format. The extension is trusted when it is
+/// known, otherwise the content of the file is sniffed
+fn is_image_file(path: &Path, formats: &[ImageFormat]) -> bool {
+    if let Some(format) = get_extension_format(path) {
+        return formats.contains(&format);
+    }
+
+    match sniff_image_format(path) {
+        Ok(Some(format)) => formats.contains(&format),
+        Ok(None) => false,
+        Err(e) => {
+            warn!("failed to read {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+pub fn load_decode_cache() -> Result<DecodeCache> {
+    let cache_path = get_cache_dir()?.join(DECODE_CACHE_FILE);
+    if !cache_path.exists() {
+        return Ok(DecodeCache::default());
+    }
+
+    let data = fs::read_to_string(&cache_path)?;
+    match serde_json::from_str(&data) {
+        Ok(cache) => Ok(cache),
+        Err(e) => {
+            warn!(
+                "ignoring invalid decode cache {}: {}",
+                cache_path.display(),
+                e
+            );
+            Ok(DecodeCache::default())
+        }
+    }
+}
+
+pub fn save_decode_cache(cache: &DecodeCache) -> Result<()> {
+    let cache_dir = get_cache_dir()?;
+    fs::create_dir_all(&cache_dir)?;
+
+    let json = serde_json::to_string(cache)?;
+    fs::write(cache_dir.join(DECODE_CACHE_FILE), json)?;
+    Ok(())
+}
+
+/// Load the ratings learned by `kanumi duel`, kept in the data directory as they cannot be rebuilt
+pub fn load_duel_ratings() -> Result<DuelRatings> {
+    let ratings_path = get_data_dir()?.join(DUEL_RATINGS_FILE);
+    if !ratings_path.exists() {
+        return Ok(DuelRatings::default());
+    }
+
+    let data = fs::read_to_string(&ratings_path)?;
+    serde_json::from_str(&data).context(format!(
+        "failed to parse duel ratings: {}",
+        ratings_path.display()
+    ))
+}
+
+pub fn save_duel_ratings(ratings: &DuelRatings) -> Result<()> {
+    let data_dir = get_data_dir()?;
+    fs::create_dir_all(&data_dir)?;
+
+    let json = serde_json::to_string(ratings)?;
+    let ratings_path = data_dir.join(DUEL_RATINGS_FILE);
+    let tmp_path = append_to_path(&ratings_path, ".tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, &ratings_path)?;
+    Ok(())
+}
+
+/// Fully decode an image, reusing the cached result if the file did not change since
+pub fn check_image_decodes(path: &Path, cache: &mut DecodeCache) -> Result<DecodeResult> {
+    let (size, modified) = match get_size_and_modified(path) {
+        Ok(state) => state,
+        Err(e) => {
+            return Ok(DecodeResult {
+                status: DecodeStatus::Unreadable,
+                error: Some(e.to_string()),
+                size: 0,
+                modified: DateTime::UNIX_EPOCH,
+            });
+        }
+    };
+
+    if let Some(cached) = cache.entries.get(path) {
+        if cached.size == size && cached.modified == modified {
+            debug!("using cached decode result for: {}", path.display());
+            return Ok(cached.clone());
+        }
+    }
+
+    debug!("decoding: {}", path.display());
+    let (status, error) = match decode_image(path) {
+        Ok(Some(ImageFormat::Jpeg)) => match has_jpeg_end_marker(path) {
+            Ok(true) => (DecodeStatus::Ok, None),
+            Ok(false) => (
+                DecodeStatus::Truncated,
+                Some(String::from("missing JPEG end of image marker")),
+            ),
+            Err(e) => (DecodeStatus::Unreadable, Some(e.to_string())),
+        },
+        Ok(_) => (DecodeStatus::Ok, None),
+        Err(e) => (get_decode_status(&e), Some(e.to_string())),
+    };
+
+    let result = DecodeResult {
+        status,
+        error,
+        size,
+        modified,
+    };
+
+    cache.entries.insert(path.to_path_buf(), result.clone());
+    Ok(result)
+}
+
+fn get_size_and_modified(path: &Path) -> io::Result<(u64, DateTime<Utc>)> {
+    let file_meta = fs::metadata(path)?;
+    Ok((file_meta.len(), DateTime::from(file_meta.modified()?)))
+}
+
+/// Decode an image, returning the format it was decoded as
+fn decode_image(path: &Path) -> Result<Option<ImageFormat>, ImageError> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader.format();
+    reader.decode()?;
+    Ok(format)
+}
+
+/// Whether a JPEG file ends with an end of image marker, ignoring padding. The JPEG decoder
+/// fills in the missing part of truncated files without returning an error
+fn has_jpeg_end_marker(path: &Path) -> Result<bool> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(JPEG_TAIL_LENGTH)))?;
+
+    let mut tail = vec![];
+    file.read_to_end(&mut tail)?;
+    while tail.last() == Some(&0) {
+        tail.pop();
+    }
+
+    Ok(tail.ends_with(&[0xff, 0xd9]))
+}
+
+fn get_decode_status(error: &ImageError) -> DecodeStatus {
+    match error {
+        ImageError::Unsupported(_) => DecodeStatus::Unsupported,
+        ImageError::IoError(e) if e.kind() == ErrorKind::UnexpectedEof => DecodeStatus::Truncated,
+        _ => DecodeStatus::Corrupt,
+    }
 }
 
 pub fn compute_blake3_hash(file: &Path) -> Result<String> {
@@ -225,6 +858,78 @@ pub fn compute_blake3_hash(file: &Path) -> Result<String> {
     Ok(hash.to_string())
 }
 
+/// Group byte-identical files by blake3 hash. Only files sharing their size with another one
+/// are hashed, unless their hash is already known, and hard links to the same file are counted once.
+/// Files that cannot be read are skipped
+pub fn find_identical_files(
+    paths: &[PathBuf],
+    known_hashes: &HashMap<&Path, String>,
+) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    let mut sizes: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for path in paths.iter() {
+        match fs::metadata(path) {
+            Ok(metadata) => sizes.entry(metadata.len()).or_default().push(path),
+            Err(e) => warn!("cannot read {}: {e}", path.display()),
+        }
+    }
+
+    let mut hashes: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut seen_files = HashSet::new();
+    for same_size_paths in sizes.into_values().filter(|paths| paths.len() > 1) {
+        for path in same_size_paths {
+            match get_file_identity(path) {
+                Ok(Some(identity)) if !seen_files.insert(identity) => {
+                    debug!("skipping hard link: {}", path.display());
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("cannot read {}: {e}", path.display());
+                    continue;
+                }
+            }
+
+            let hash = match known_hashes.get(path.as_path()) {
+                Some(hash) => hash.clone(),
+                None => match compute_blake3_hash(path) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        warn!("cannot read {}: {e}", path.display());
+                        continue;
+                    }
+                },
+            };
+            hashes.entry(hash).or_default().push(path.clone());
+        }
+    }
+
+    let mut groups: Vec<_> = hashes
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(hash, mut paths)| {
+            paths.sort();
+            (hash, paths)
+        })
+        .collect();
+    groups.sort_by(|(_, a), (_, b)| a[0].cmp(&b[0]));
+
+    Ok(groups)
+}
+
+/// Device and inode of a file, shared by all of its hard links
+#[cfg(unix)]
+fn get_file_identity(path: &Path) -> Result<Option<(u64, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path)?;
+    Ok(Some((metadata.dev(), metadata.ino())))
+}
+
+#[cfg(not(unix))]
+fn get_file_identity(_path: &Path) -> Result<Option<(u64, u64)>> {
+    Ok(None)
+}
+
 pub fn create_banner(text: &str) -> String {
     let center_part = format!("# {text} #\n");
 
@@ -235,6 +940,52 @@ pub fn create_banner(text: &str) -> String {
     format!("{outline}{empty}{center_part}{empty}{outline}")
 }
 
+/// Number of single character insertions, deletions or substitutions to turn a string into another
+pub fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Find the name closest to a mistyped one, if any is close enough to be suggested
+pub fn find_closest_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (candidate, get_edit_distance(name, candidate)))
+        .filter(|(_, distance)| *distance <= max_distance)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| candidate)
+}
+
+/// Get the path of an image from its metadata ID or path, or from the path of any image file
+pub fn resolve_image_path(identifier: &str, metadatas: &[ImageMeta]) -> Result<PathBuf> {
+    if let Some(meta) = get_image_by_path_or_id(identifier, metadatas)? {
+        return Ok(meta.path.clone());
+    }
+
+    let path = expand_path(Path::new(identifier))?;
+    ensure!(
+        path.is_file(),
+        "no matching metadata or image for: {identifier}"
+    );
+    Ok(std::path::absolute(path)?)
+}
+
 pub fn get_image_by_path_or_id<'a>(
     identifier: &str,
     metadatas: &'a [ImageMeta],
@@ -246,3 +997,174 @@ pub fn get_image_by_path_or_id<'a>(
 
     Ok(metadatas.iter().find(|m| m.id == identifier))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+    use std::process;
+
+    /// Write an image with enough detail that truncating it cuts through the compressed data
+    fn write_test_image(name: &str, format: ImageFormat) -> PathBuf {
+        let image = RgbImage::from_fn(256, 256, |x, y| {
+            Rgb([
+                (x * 7 % 256) as u8,
+                (y * 13 % 256) as u8,
+                ((x ^ y) % 256) as u8,
+            ])
+        });
+
+        let path = env::temp_dir().join(format!("kanumi-test-{}-{name}", process::id()));
+        image.save_with_format(&path, format).unwrap();
+        path
+    }
+
+    fn truncate_file(path: &Path) {
+        let data = fs::read(path).unwrap();
+        fs::write(path, &data[..data.len() / 2]).unwrap();
+    }
+
+    #[test]
+    fn detects_truncated_jpeg() {
+        let path = write_test_image("truncated.jpg", ImageFormat::Jpeg);
+        let mut cache = DecodeCache::default();
+        assert_eq!(
+            check_image_decodes(&path, &mut cache).unwrap().status,
+            DecodeStatus::Ok
+        );
+
+        truncate_file(&path);
+        let result = check_image_decodes(&path, &mut cache).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.status, DecodeStatus::Truncated);
+    }
+
+    #[test]
+    fn detects_truncated_png() {
+        let path = write_test_image("truncated.png", ImageFormat::Png);
+        truncate_file(&path);
+
+        let result = check_image_decodes(&path, &mut DecodeCache::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.status, DecodeStatus::Truncated);
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let path = env::temp_dir().join(format!("kanumi-test-{}-missing.png", process::id()));
+        let mut cache = DecodeCache::default();
+
+        let result = check_image_decodes(&path, &mut cache).unwrap();
+        assert_eq!(result.status, DecodeStatus::Unreadable);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn skips_unreadable_files_when_finding_identical_ones() {
+        let dir = env::temp_dir().join(format!("kanumi-test-{}-identical", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // A directory of the same size as the copies passes the size check but cannot be hashed
+        let unreadable_dir = dir.join("directory.png");
+        fs::create_dir_all(&unreadable_dir).unwrap();
+        let size = fs::metadata(&unreadable_dir).unwrap().len() as usize;
+
+        let (first, second) = (dir.join("first.png"), dir.join("second.png"));
+        fs::write(&first, vec![7u8; size]).unwrap();
+        fs::write(&second, vec![7u8; size]).unwrap();
+
+        let paths = [
+            first.clone(),
+            dir.join("missing.png"),
+            unreadable_dir,
+            second.clone(),
+        ];
+        let groups = find_identical_files(&paths, &HashMap::new()).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, compute_blake3_hash(&first).unwrap());
+        assert_eq!(groups[0].1, vec![first, second]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::seconds(30));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::minutes(15));
+        assert_eq!(parse_duration(" 2h ").unwrap(), Duration::hours(2));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::days(7));
+        assert_eq!(parse_duration("1w").unwrap(), Duration::weeks(1));
+        assert_eq!(parse_duration("0s").unwrap(), Duration::zero());
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for input in [
+            "",
+            "10",
+            "h",
+            "-5m",
+            "1.5h",
+            "10y",
+            "10 m",
+            "5mm",
+            "99999999999999w",
+        ] {
+            assert!(
+                parse_duration(input).is_err(),
+                "`{input}` should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("512B").unwrap(), 512);
+        assert_eq!(parse_size("10KB").unwrap(), 10_000);
+        assert_eq!(parse_size("10kb").unwrap(), 10_000);
+        assert_eq!(parse_size("3MB").unwrap(), 3_000_000);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1TiB").unwrap(), 1 << 40);
+    }
+
+    #[test]
+    fn rejects_malformed_sizes() {
+        for input in ["", "MB", "-1KB", "1.5MB", "10PB", "10 MB", "99999999999TB"] {
+            assert!(parse_size(input).is_err(), "`{input}` should be invalid");
+        }
+    }
+
+    #[test]
+    fn parses_datetimes() {
+        let expected = NaiveDate::from_ymd_opt(2026, 12, 24)
+            .unwrap()
+            .and_hms_opt(21, 0, 0)
+            .unwrap();
+        assert_eq!(parse_datetime("2026-12-24T21:00").unwrap(), expected);
+        assert_eq!(parse_datetime("2026-12-24T21:00:00").unwrap(), expected);
+        assert_eq!(parse_datetime("2026-12-24 21:00").unwrap(), expected);
+
+        let midnight = NaiveDate::from_ymd_opt(2026, 12, 24)
+            .unwrap()
+            .and_time(NaiveTime::MIN);
+        assert_eq!(parse_datetime("2026-12-24").unwrap(), midnight);
+    }
+
+    #[test]
+    fn rejects_malformed_datetimes() {
+        for input in [
+            "",
+            "2026-13-01",
+            "2026-02-30",
+            "24/12/2026",
+            "2026-12-24T25:00",
+            "21:00",
+        ] {
+            assert!(
+                parse_datetime(input).is_err(),
+                "`{input}` should be invalid"
+            );
+        }
+    }
+}
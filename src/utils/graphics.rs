@@ -0,0 +1,230 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{ImageFormat, Rgba, RgbaImage};
+use std::{fmt::Write, io::Cursor};
+
+/// Delete every image shown with the kitty graphics protocol
+pub const KITTY_DELETE_ALL: &str = "\x1b_Ga=d\x1b\\";
+
+/// Maximum size of the base64 payload of a kitty graphics escape sequence
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Number of levels of each channel in the sixel palette (6 × 6 × 6 = 216 colors)
+const SIXEL_LEVELS: u32 = 6;
+
+/// Pixels with a lower alpha are left transparent
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Encode an image with the kitty graphics protocol, displayed over the given number of cells
+pub fn encode_kitty(image: &RgbaImage, columns: u32, rows: u32) -> Result<String> {
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageFormat::Png)?;
+    let payload = STANDARD.encode(png.into_inner());
+    write_kitty_chunks(&payload, columns, rows)
+}
+
+/// Split a base64 payload into kitty graphics escape sequences, all but the last announcing more
+fn write_kitty_chunks(payload: &str, columns: u32, rows: u32) -> Result<String> {
+    let chunks: Vec<&[u8]> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut output = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk)?;
+        match index {
+            0 => write!(
+                output,
+                "\x1b_Gf=100,a=T,c={columns},r={rows},m={more};{chunk}\x1b\\"
+            )?,
+            _ => write!(output, "\x1b_Gm={more};{chunk}\x1b\\")?,
+        }
+    }
+
+    Ok(output)
+}
+
+/// Encode an image as sixels, with colors reduced to a fixed palette
+pub fn encode_sixel(image: &RgbaImage) -> Result<String> {
+    let (width, height) = image.dimensions();
+    let mut output = format!("\x1bP0;1;0q\"1;1;{width};{height}");
+
+    for index in 0..SIXEL_LEVELS.pow(3) {
+        let [r, g, b] = [
+            index / (SIXEL_LEVELS * SIXEL_LEVELS),
+            index / SIXEL_LEVELS % SIXEL_LEVELS,
+            index % SIXEL_LEVELS,
+        ]
+        .map(|level| level * 100 / (SIXEL_LEVELS - 1));
+        write!(output, "#{index};2;{r};{g};{b}")?;
+    }
+
+    let get_color_index = |pixel: &Rgba<u8>| {
+        let [r, g, b, a] = pixel.0;
+        let level = |channel: u8| (u32::from(channel) * (SIXEL_LEVELS - 1) + 127) / 255;
+        (a >= ALPHA_THRESHOLD)
+            .then(|| level(r) * SIXEL_LEVELS * SIXEL_LEVELS + level(g) * SIXEL_LEVELS + level(b))
+    };
+
+    // Each band is 6 pixels high, drawn once per color it contains
+    for band_top in (0..height).step_by(6) {
+        let band_height = (height - band_top).min(6);
+        let mut bands: Vec<Option<Vec<u8>>> = vec![None; SIXEL_LEVELS.pow(3) as usize];
+        for x in 0..width {
+            for dy in 0..band_height {
+                if let Some(index) = get_color_index(image.get_pixel(x, band_top + dy)) {
+                    let band = bands[index as usize].get_or_insert_with(|| vec![0; width as usize]);
+                    band[x as usize] |= 1 << dy;
+                }
+            }
+        }
+
+        for (index, band) in bands.iter().enumerate() {
+            if let Some(band) = band {
+                write!(output, "#{index}")?;
+                write_sixel_runs(&mut output, band)?;
+                output.push('$');
+            }
+        }
+
+        output.push('-');
+    }
+
+    output.push_str("\x1b\\");
+    Ok(output)
+}
+
+/// Write the sixels of a band, with repeated ones run-length encoded
+fn write_sixel_runs(output: &mut String, band: &[u8]) -> Result<()> {
+    let mut x = 0;
+    while x < band.len() {
+        let bits = band[x];
+        let run = band[x..].iter().take_while(|other| **other == bits).count();
+        let sixel = char::from(0x3f + bits);
+        match run {
+            1..=3 => (0..run).for_each(|_| output.push(sixel)),
+            _ => write!(output, "!{run}{sixel}")?,
+        }
+
+        x += run;
+    }
+
+    Ok(())
+}
+
+/// Render an image with Unicode half blocks, two pixels per cell, in true colors
+pub fn encode_half_blocks(image: &RgbaImage) -> Result<String> {
+    let (width, height) = image.dimensions();
+    let mut output = String::new();
+
+    for top in (0..height).step_by(2) {
+        for x in 0..width {
+            let upper = image.get_pixel(x, top);
+            let lower = (top + 1 < height).then(|| image.get_pixel(x, top + 1));
+            let is_visible = |pixel: &Rgba<u8>| pixel.0[3] >= ALPHA_THRESHOLD;
+
+            match (is_visible(upper), lower.filter(|pixel| is_visible(pixel))) {
+                (true, Some(lower)) => {
+                    let [r, g, b, _] = upper.0;
+                    let [lr, lg, lb, _] = lower.0;
+                    write!(output, "\x1b[38;2;{r};{g};{b};48;2;{lr};{lg};{lb}m▀")?;
+                }
+                (true, None) => {
+                    let [r, g, b, _] = upper.0;
+                    write!(output, "\x1b[0;38;2;{r};{g};{b}m▀")?;
+                }
+                (false, Some(lower)) => {
+                    let [r, g, b, _] = lower.0;
+                    write!(output, "\x1b[0;38;2;{r};{g};{b}m▄")?;
+                }
+                (false, None) => output.push_str("\x1b[0m "),
+            }
+        }
+
+        output.push_str("\x1b[0m\n");
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    #[test]
+    fn renders_half_blocks_of_two_pixel_rows() {
+        let image = RgbaImage::from_fn(2, 3, |x, y| match (x, y) {
+            (0, 0) => RED,
+            (0, 1) => BLUE,
+            (1, 0) | (1, 2) => TRANSPARENT,
+            (1, 1) | (0, 2) => Rgba([0, 255, 0, 255]),
+            _ => unreachable!(),
+        });
+
+        let expected = concat!(
+            "\x1b[38;2;255;0;0;48;2;0;0;255m▀\x1b[0;38;2;0;255;0m▄\x1b[0m\n",
+            "\x1b[0;38;2;0;255;0m▀\x1b[0m \x1b[0m\n",
+        );
+        assert_eq!(encode_half_blocks(&image).unwrap(), expected);
+    }
+
+    #[test]
+    fn splits_kitty_payload_in_chunks() {
+        let payload = "A".repeat(KITTY_CHUNK_SIZE);
+        assert_eq!(
+            write_kitty_chunks(&payload, 4, 2).unwrap(),
+            format!("\x1b_Gf=100,a=T,c=4,r=2,m=0;{payload}\x1b\\")
+        );
+
+        let payload = format!("{}B", "A".repeat(KITTY_CHUNK_SIZE));
+        assert_eq!(
+            write_kitty_chunks(&payload, 4, 2).unwrap(),
+            format!(
+                "\x1b_Gf=100,a=T,c=4,r=2,m=1;{}\x1b\\\x1b_Gm=0;B\x1b\\",
+                "A".repeat(KITTY_CHUNK_SIZE)
+            )
+        );
+    }
+
+    #[test]
+    fn encodes_kitty_image_as_png() {
+        // Noise does not compress, so that the payload spans several chunks
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            let noise = (x * 7919 + y * 104_729).wrapping_mul(2_654_435_761);
+            Rgba(noise.to_le_bytes())
+        });
+        let output = encode_kitty(&image, 8, 4).unwrap();
+        assert!(output.starts_with("\x1b_Gf=100,a=T,c=8,r=4,m=1;"));
+        assert!(output.contains("\x1b_Gm=1;"));
+
+        let payload: String = output
+            .split("\x1b\\")
+            .filter(|sequence| !sequence.is_empty())
+            .map(|sequence| sequence.split_once(';').unwrap().1)
+            .collect();
+        let png = STANDARD.decode(payload).unwrap();
+        let decoded = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+        assert_eq!(decoded.to_rgba8(), image);
+    }
+
+    #[test]
+    fn encodes_sixel_header_palette_and_bands() {
+        let image = RgbaImage::from_fn(5, 2, |x, y| match (x, y) {
+            (0, 0) => BLUE,
+            (0, 1) => TRANSPARENT,
+            _ => RED,
+        });
+        let output = encode_sixel(&image).unwrap();
+
+        let palette: String = (0..216)
+            .map(|index| {
+                let [r, g, b] = [index / 36, index / 6 % 6, index % 6].map(|level| level * 20);
+                format!("#{index};2;{r};{g};{b}")
+            })
+            .collect();
+        let expected = format!("\x1bP0;1;0q\"1;1;5;2{palette}#5@!4?$#180?!4B$-\x1b\\");
+        assert_eq!(output, expected);
+    }
+}
@@ -1 +1,4 @@
 pub mod common;
+pub mod graphics;
+pub mod palette;
+pub mod thumbnail;
@@ -0,0 +1,437 @@
+use anyhow::{bail, ensure, Context, Result};
+use image::{ImageReader, RgbaImage};
+use log::debug;
+use std::{fs, path::Path};
+
+use crate::models::{Color, ColorTheme, ConfigurationTheme};
+
+/// Number of clusters the colors of an image are quantized to
+const PALETTE_SIZE: usize = 5;
+
+/// Maximum side of the thumbnail that pixels are sampled from
+const SAMPLE_SIZE: u32 = 64;
+
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Minimum share of the pixels a cluster must cover to count as a dominant color
+const DOMINANT_THRESHOLD: f32 = 0.1;
+
+/// Color of a cluster of pixels, along with the share of the image it covers
+#[derive(Debug, Clone, Copy)]
+pub struct PaletteColor {
+    pub rgb: [u8; 3],
+    pub proportion: f32,
+}
+
+/// Decode an image into a thumbnail that is small enough to be analyzed quickly
+pub fn load_sample(image: &Path) -> Result<RgbaImage> {
+    let image = ImageReader::open(image)?.with_guessed_format()?.decode()?;
+    Ok(image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE).to_rgba8())
+}
+
+/// Quantize the colors of a sample with k-means, most dominant cluster first
+pub fn extract_palette(sample: &RgbaImage) -> Vec<PaletteColor> {
+    let mut pixels: Vec<[f32; 3]> = get_visible_pixels(sample)
+        .map(|rgb| rgb.map(|channel| channel as f32))
+        .collect();
+
+    if pixels.is_empty() {
+        return vec![];
+    }
+
+    // Spread initial centroids over the luminance range, so that results are deterministic
+    pixels.sort_by(|a, b| get_luminance(a).total_cmp(&get_luminance(b)));
+    let cluster_count = PALETTE_SIZE.min(pixels.len());
+    let mut centroids: Vec<[f32; 3]> = (0..cluster_count)
+        .map(|i| pixels[(2 * i + 1) * pixels.len() / (2 * cluster_count)])
+        .collect();
+
+    let mut assignments = vec![0; pixels.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (pixel, assignment) in pixels.iter().zip(assignments.iter_mut()) {
+            let nearest = get_nearest_index(&centroids, pixel);
+            if nearest != *assignment {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+
+        let mut sums = vec![[0.0; 3]; centroids.len()];
+        let mut counts = vec![0; centroids.len()];
+        for (pixel, assignment) in pixels.iter().zip(assignments.iter()) {
+            for channel in 0..3 {
+                sums[*assignment][channel] += pixel[channel];
+            }
+            counts[*assignment] += 1;
+        }
+
+        for (index, centroid) in centroids.iter_mut().enumerate() {
+            if counts[index] > 0 {
+                *centroid = sums[index].map(|sum| sum / counts[index] as f32);
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let mut palette: Vec<PaletteColor> = vec![];
+    for (index, centroid) in centroids.iter().enumerate() {
+        let count = assignments.iter().filter(|a| **a == index).count();
+        if count == 0 {
+            continue;
+        }
+
+        let rgb = centroid.map(|channel| channel.round() as u8);
+        let proportion = count as f32 / pixels.len() as f32;
+        match palette.iter_mut().find(|color| color.rgb == rgb) {
+            Some(color) => color.proportion += proportion,
+            None => palette.push(PaletteColor { rgb, proportion }),
+        }
+    }
+
+    palette.sort_by(|a, b| b.proportion.total_cmp(&a.proportion));
+    palette
+}
+
+/// Classify a sample as light or dark from the mean and distribution of its lightness.
+/// Confidence grows with the distance between the mean and the threshold, and with the share
+/// of pixels that agree with the mean. Below the minimum confidence, no theme is returned
+pub fn classify_theme(
+    sample: &RgbaImage,
+    settings: &ConfigurationTheme,
+) -> (Option<ColorTheme>, f64) {
+    let lightness: Vec<f64> = get_visible_pixels(sample)
+        .map(|rgb| to_oklab(rgb)[0] as f64)
+        .collect();
+
+    if lightness.is_empty() {
+        return (None, 0.0);
+    }
+
+    let mean = lightness.iter().sum::<f64>() / lightness.len() as f64;
+    let is_dark = mean < settings.threshold;
+    let agreeing_count = lightness
+        .iter()
+        .filter(|value| (**value < settings.threshold) == is_dark)
+        .count();
+
+    let distance = match is_dark {
+        true => (settings.threshold - mean) / settings.threshold,
+        false => (mean - settings.threshold) / (1.0 - settings.threshold),
+    };
+    let agreement = (agreeing_count as f64 / lightness.len() as f64 - 0.5) * 2.0;
+    let confidence = ((distance + agreement.max(0.0)) / 2.0).clamp(0.0, 1.0);
+    let confidence = (confidence * 100.0).round() / 100.0;
+
+    if confidence < settings.min_confidence {
+        return (None, confidence);
+    }
+
+    let theme = match is_dark {
+        true => ColorTheme::Dark,
+        false => ColorTheme::Light,
+    };
+
+    (Some(theme), confidence)
+}
+
+/// Map the clusters covering a large enough share of the image to the nearest named colors
+pub fn get_dominant_colors(palette: &[PaletteColor]) -> Vec<Color> {
+    let mut colors = vec![];
+    for (index, palette_color) in palette.iter().enumerate() {
+        if index > 0 && palette_color.proportion < DOMINANT_THRESHOLD {
+            continue;
+        }
+
+        let color = get_nearest_color(palette_color.rgb);
+        if !colors.contains(&color) {
+            colors.push(color);
+        }
+    }
+
+    colors
+}
+
+/// Named color that is perceptually the closest to the given one
+pub fn get_nearest_color(rgb: [u8; 3]) -> Color {
+    let lab = to_oklab(rgb);
+    Color::ALL
+        .into_iter()
+        .min_by(|a, b| {
+            let a_distance = get_oklab_distance(&lab, &to_oklab(a.reference_rgb()));
+            let b_distance = get_oklab_distance(&lab, &to_oklab(b.reference_rgb()));
+            a_distance.total_cmp(&b_distance)
+        })
+        .expect("there should be named colors")
+}
+
+pub fn format_hex(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+/// Parse a color written as `#rrggbb` or `#rgb`, the `#` being optional
+pub fn parse_hex(input: &str) -> Result<[u8; 3]> {
+    let hex = input.trim().trim_start_matches('#');
+    ensure!(
+        hex.chars().all(|c| c.is_ascii_hexdigit()),
+        "invalid hex color: `{input}`"
+    );
+
+    let expanded: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_owned(),
+        _ => bail!("invalid hex color: `{input}`, expected `#rrggbb` or `#rgb`"),
+    };
+
+    let mut rgb = [0; 3];
+    for (channel, value) in rgb.iter_mut().enumerate() {
+        *value = u8::from_str_radix(&expanded[channel * 2..channel * 2 + 2], 16)
+            .context(format!("invalid hex color: `{input}`"))?;
+    }
+
+    Ok(rgb)
+}
+
+/// Load the colors of a scheme from a JSON file: either an array of hex colors, or an object
+/// such as a terminal theme, where every string value that is a hex color is used
+pub fn load_palette_file(path: &Path) -> Result<Vec<[u8; 3]>> {
+    let content = fs::read_to_string(path)
+        .context(format!("failed to read palette file: {}", path.display()))?;
+    let value: serde_json::Value = serde_json::from_str(&content)
+        .context(format!("invalid palette file: {}", path.display()))?;
+
+    let mut colors = vec![];
+    collect_hex_colors(&value, &mut colors);
+    ensure!(
+        !colors.is_empty(),
+        "no hex color found in palette file: {}",
+        path.display()
+    );
+
+    debug!("loaded {} colors from: {}", colors.len(), path.display());
+    Ok(colors)
+}
+
+fn collect_hex_colors(value: &serde_json::Value, colors: &mut Vec<[u8; 3]>) {
+    match value {
+        serde_json::Value::String(text) if text.starts_with('#') => {
+            if let Ok(rgb) = parse_hex(text) {
+                if !colors.contains(&rgb) {
+                    colors.push(rgb);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values.iter() {
+                collect_hex_colors(value, colors);
+            }
+        }
+        serde_json::Value::Object(values) => {
+            for value in values.values() {
+                collect_hex_colors(value, colors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Perceptual distance between two colors: the Oklab distance scaled to 0-100,
+/// where about 2 is barely noticeable
+pub fn get_color_distance(a: [u8; 3], b: [u8; 3]) -> f32 {
+    get_oklab_distance(&to_oklab(a), &to_oklab(b)) * 100.0
+}
+
+/// Convert an sRGB color to the Oklab color space, where distances match perceived differences
+pub fn to_oklab(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|channel| {
+        let channel = channel as f32 / 255.0;
+        match channel <= 0.04045 {
+            true => channel / 12.92,
+            false => ((channel + 0.055) / 1.055).powf(2.4),
+        }
+    });
+
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+pub fn get_oklab_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Colors of the pixels of a sample, leaving out transparent ones since they are not seen
+fn get_visible_pixels(sample: &RgbaImage) -> impl Iterator<Item = [u8; 3]> + '_ {
+    sample
+        .pixels()
+        .filter(|pixel| pixel[3] >= 128)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+}
+
+fn get_luminance(rgb: &[f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+fn get_nearest_index(centroids: &[[f32; 3]], pixel: &[f32; 3]) -> usize {
+    let squared_distance = |centroid: &[f32; 3]| -> f32 {
+        (0..3)
+            .map(|channel| (centroid[channel] - pixel[channel]).powi(2))
+            .sum()
+    };
+
+    (0..centroids.len())
+        .min_by(|a, b| {
+            squared_distance(&centroids[*a]).total_cmp(&squared_distance(&centroids[*b]))
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const RED: [u8; 3] = [215, 40, 40];
+    const BLUE: [u8; 3] = [40, 90, 210];
+
+    /// Sample whose top three quarters are red and bottom quarter is blue
+    fn two_color_sample() -> RgbaImage {
+        RgbaImage::from_fn(8, 8, |_, y| match y < 6 {
+            true => Rgba([RED[0], RED[1], RED[2], 255]),
+            false => Rgba([BLUE[0], BLUE[1], BLUE[2], 255]),
+        })
+    }
+
+    fn get_colors(palette: &[PaletteColor]) -> Vec<([u8; 3], f32)> {
+        palette
+            .iter()
+            .map(|color| (color.rgb, color.proportion))
+            .collect()
+    }
+
+    #[test]
+    fn extracts_dominant_colors_of_two_color_image() {
+        let palette = extract_palette(&two_color_sample());
+        assert_eq!(get_colors(&palette), vec![(RED, 0.75), (BLUE, 0.25)]);
+        assert_eq!(get_dominant_colors(&palette), vec![Color::Red, Color::Blue]);
+    }
+
+    #[test]
+    fn extracts_the_same_palette_every_time() {
+        let sample = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, ((x + y) * 8) as u8, 255])
+        });
+
+        let palette = get_colors(&extract_palette(&sample));
+        assert_eq!(palette.len(), PALETTE_SIZE);
+        for _ in 0..3 {
+            assert_eq!(get_colors(&extract_palette(&sample)), palette);
+        }
+    }
+
+    #[test]
+    fn ignores_transparent_pixels() {
+        let transparent = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 0]));
+        assert!(extract_palette(&transparent).is_empty());
+        assert!(get_dominant_colors(&extract_palette(&transparent)).is_empty());
+
+        let mut sample = two_color_sample();
+        for pixel in sample.pixels_mut().filter(|pixel| pixel[2] == BLUE[2]) {
+            pixel[3] = 0;
+        }
+        assert_eq!(get_colors(&extract_palette(&sample)), vec![(RED, 1.0)]);
+    }
+
+    #[test]
+    fn extracts_palette_of_single_pixel() {
+        let sample = RgbaImage::from_pixel(1, 1, Rgba([240, 140, 30, 255]));
+        let palette = extract_palette(&sample);
+        assert_eq!(get_colors(&palette), vec![([240, 140, 30], 1.0)]);
+        assert_eq!(get_dominant_colors(&palette), vec![Color::Orange]);
+    }
+
+    #[test]
+    fn classifies_light_and_dark_images() {
+        let settings = ConfigurationTheme::default();
+
+        let white = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]));
+        assert_eq!(
+            classify_theme(&white, &settings),
+            (Some(ColorTheme::Light), 1.0)
+        );
+
+        let black = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        assert_eq!(
+            classify_theme(&black, &settings),
+            (Some(ColorTheme::Dark), 1.0)
+        );
+
+        // A few light pixels lower the confidence without changing the theme
+        let night = RgbaImage::from_fn(4, 4, |x, y| match (x, y) {
+            (0, 0) => Rgba([255, 255, 255, 255]),
+            _ => Rgba([20, 20, 30, 255]),
+        });
+        let (theme, confidence) = classify_theme(&night, &settings);
+        assert_eq!(theme, Some(ColorTheme::Dark));
+        assert!(confidence > settings.min_confidence && confidence < 1.0);
+    }
+
+    #[test]
+    fn leaves_borderline_images_unclassified() {
+        let settings = ConfigurationTheme::default();
+        let checkerboard = RgbaImage::from_fn(4, 4, |x, y| match (x + y) % 2 == 0 {
+            true => Rgba([255, 255, 255, 255]),
+            false => Rgba([0, 0, 0, 255]),
+        });
+
+        let (theme, confidence) = classify_theme(&checkerboard, &settings);
+        assert_eq!(theme, None);
+        assert!(confidence < settings.min_confidence);
+
+        let transparent = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 0]));
+        assert_eq!(classify_theme(&transparent, &settings), (None, 0.0));
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse_hex("#aabbcc").unwrap(), [0xaa, 0xbb, 0xcc]);
+        assert_eq!(parse_hex("AABBCC").unwrap(), [0xaa, 0xbb, 0xcc]);
+        assert_eq!(parse_hex("#abc").unwrap(), [0xaa, 0xbb, 0xcc]);
+        assert_eq!(parse_hex(" abc ").unwrap(), [0xaa, 0xbb, 0xcc]);
+        assert_eq!(parse_hex(&format_hex([1, 2, 255])).unwrap(), [1, 2, 255]);
+    }
+
+    #[test]
+    fn rejects_malformed_hex_colors() {
+        for input in [
+            "",
+            "#",
+            "#ab",
+            "#abcd",
+            "#aabbccdd",
+            "#ggg",
+            "#aabbcg",
+            "+abc",
+            "#ab c",
+        ] {
+            assert!(parse_hex(input).is_err(), "`{input}` should be invalid");
+        }
+    }
+
+    #[test]
+    fn measures_color_distance() {
+        assert_eq!(get_color_distance(RED, RED), 0.0);
+        assert_eq!(get_color_distance(RED, BLUE), get_color_distance(BLUE, RED));
+        assert!(get_color_distance(RED, [220, 45, 40]) < 2.0);
+        assert!(get_color_distance([0, 0, 0], [255, 255, 255]) > 99.0);
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use directories::BaseDirs;
+use image::ImageReader;
+use log::debug;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{self, Path, PathBuf},
+    process,
+    time::UNIX_EPOCH,
+};
+
+use crate::models::ThumbnailSize;
+
+/// Characters escaped in file URIs, as GLib does, so that names match the ones of other apps
+const URI_ESCAPED: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+const URI_KEY: &str = "Thumb::URI";
+const MTIME_KEY: &str = "Thumb::MTime";
+const SIZE_KEY: &str = "Thumb::Size";
+
+/// Get the thumbnail cache shared with other apps, `$XDG_CACHE_HOME/thumbnails`
+pub fn get_thumbnails_dir() -> Result<PathBuf> {
+    let base_dirs = BaseDirs::new().context("could not get cache directory")?;
+    Ok(base_dirs.cache_dir().join("thumbnails"))
+}
+
+/// Get the `file://` URI of an image, which identifies its thumbnails
+pub fn get_file_uri(image: &Path) -> Result<String> {
+    let image = path::absolute(image)?;
+    let image = image.to_str().context(format!(
+        "image path should be valid UTF-8: {}",
+        image.display()
+    ))?;
+
+    Ok(format!(
+        "file://{}",
+        utf8_percent_encode(image, URI_ESCAPED)
+    ))
+}
+
+/// Get the path of the thumbnail of an image, named after the MD5 hash of its URI
+pub fn get_thumbnail_path(image: &Path, size: ThumbnailSize) -> Result<PathBuf> {
+    let digest = md5::compute(get_file_uri(image)?);
+    Ok(get_thumbnails_dir()?
+        .join(size.name())
+        .join(format!("{digest:x}.png")))
+}
+
+/// Find an up-to-date thumbnail of the image, of the given size or larger, created by any app
+pub fn find_thumbnail(image: &Path, size: ThumbnailSize) -> Result<Option<PathBuf>> {
+    let uri = get_file_uri(image)?;
+    let (mtime, file_size) = get_mtime_and_size(image)?;
+
+    for candidate_size in ThumbnailSize::ALL.into_iter().filter(|s| *s >= size) {
+        let thumbnail_path = get_thumbnail_path(image, candidate_size)?;
+        if is_thumbnail_valid(&thumbnail_path, &uri, mtime, file_size) {
+            debug!("found thumbnail: {}", thumbnail_path.display());
+            return Ok(Some(thumbnail_path));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Get an up-to-date thumbnail of the image, creating it when there is none
+pub fn get_or_create_thumbnail(image: &Path, size: ThumbnailSize) -> Result<PathBuf> {
+    match find_thumbnail(image, size)? {
+        Some(thumbnail_path) => Ok(thumbnail_path),
+        None => create_thumbnail(image, size),
+    }
+}
+
+/// Write the thumbnail of the image to the shared cache, with the metadata other apps expect
+pub fn create_thumbnail(image: &Path, size: ThumbnailSize) -> Result<PathBuf> {
+    let uri = get_file_uri(image)?;
+    let (mtime, file_size) = get_mtime_and_size(image)?;
+
+    let decoded = ImageReader::open(image)?.with_guessed_format()?.decode()?;
+    let (width, height) = (decoded.width(), decoded.height());
+    let thumbnail = match width > size.pixels() || height > size.pixels() {
+        true => decoded.thumbnail(size.pixels(), size.pixels()).to_rgba8(),
+        false => decoded.to_rgba8(),
+    };
+
+    let thumbnail_path = get_thumbnail_path(image, size)?;
+    let thumbnail_dir = thumbnail_path
+        .parent()
+        .context("thumbnail should have a parent directory")?;
+    create_private_dir(thumbnail_dir)?;
+
+    // Written to a temporary file first, so that other apps never read a partial thumbnail
+    let temp_path = thumbnail_dir.join(format!(".kanumi-{}.png", process::id()));
+    let file = File::create(&temp_path)?;
+    set_private_permissions(&temp_path)?;
+
+    let mut encoder =
+        png::Encoder::new(BufWriter::new(file), thumbnail.width(), thumbnail.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_text_chunk(URI_KEY.to_owned(), uri)?;
+    encoder.add_text_chunk(MTIME_KEY.to_owned(), mtime.to_string())?;
+    encoder.add_text_chunk(SIZE_KEY.to_owned(), file_size.to_string())?;
+    encoder.add_text_chunk("Thumb::Image::Width".to_owned(), width.to_string())?;
+    encoder.add_text_chunk("Thumb::Image::Height".to_owned(), height.to_string())?;
+    encoder.add_text_chunk("Software".to_owned(), String::from("kanumi"))?;
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(thumbnail.as_raw())?;
+    writer.finish()?;
+
+    fs::rename(&temp_path, &thumbnail_path)?;
+    debug!("created thumbnail: {}", thumbnail_path.display());
+    Ok(thumbnail_path)
+}
+
+/// Whether the thumbnail exists and was made from the current content of the image
+fn is_thumbnail_valid(thumbnail_path: &Path, uri: &str, mtime: u64, file_size: u64) -> bool {
+    let Ok(file) = File::open(thumbnail_path) else {
+        return false;
+    };
+
+    let Ok(reader) = png::Decoder::new(file).read_info() else {
+        return false;
+    };
+
+    let get_text = |key: &str| {
+        reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.as_str())
+    };
+
+    let mtime = mtime.to_string();
+    let file_size = file_size.to_string();
+
+    // The size is optional, but must match when present
+    get_text(URI_KEY) == Some(uri)
+        && get_text(MTIME_KEY) == Some(&mtime)
+        && get_text(SIZE_KEY).is_none_or(|size| size == file_size)
+}
+
+fn get_mtime_and_size(image: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(image)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    Ok((mtime, metadata.len()))
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_private_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_private_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}